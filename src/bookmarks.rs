@@ -0,0 +1,377 @@
+use avian3d::prelude::LinearVelocity;
+use bevy::{
+    asset::RenderAssetUsages,
+    input::{keyboard::{Key, KeyboardInput}, ButtonState, InputSystem},
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat}
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::CamFollowParams,
+    deep::ReferenceOrbit,
+    formula::Formula,
+    fractal::{FractalView, FractallBounds, JuliaParent},
+    history::RecordView,
+    palette::Palette,
+    player::{Player, PlayerCell},
+    session::{read_text, write_text},
+    shared::cell2xz,
+    GameState
+};
+
+pub struct BookmarksPlugin;
+impl Plugin for BookmarksPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .insert_resource(Bookmarks::load())
+        .init_resource::<BookmarkEdit>()
+        .add_systems(Startup, startup)
+        // runs before everything else sees the keys, so typing a name does not drive the game
+        .add_systems(PreUpdate, edit_name.after(InputSystem).run_if(|e: Res<BookmarkEdit>| e.index.is_some()))
+        .add_systems(Update, bookmark_keys.run_if(on_event::<KeyboardInput>))
+        .add_systems(Update, rebuild_panel.after(bookmark_keys).run_if(
+            resource_changed::<Bookmarks>
+            .or(resource_changed::<BookmarkEdit>)
+        ))
+        ;
+    }
+}
+
+// ---
+
+const BOOKMARKS_FILE: &str = "bookmarks.ron";
+const THUMB_WIDTH: u32 = 96;
+
+#[derive(Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub bounds: FractallBounds,
+    pub formula: Formula,
+    // view a Julia bookmark goes back to on J, bookmarks from before it go back to the formula default
+    #[serde(default)]
+    pub julia_parent: Option<FractallBounds>,
+    pub cell: (i32, i32),
+    pub camera: CamFollowParams,
+    // the bookmarked view rendered small, a base64 png keeps the file and the browser storage small
+    thumb_size: (u32, u32),
+    thumb: String,
+    #[serde(skip)]
+    thumb_h: Option<Handle<Image>>
+}
+
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct Bookmarks(pub Vec<Bookmark>);
+
+impl Bookmarks {
+    fn load() -> Self {
+        read_text(BOOKMARKS_FILE).ok()
+        .and_then(|text| ron::from_str(&text).ok())
+        .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let res = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())
+        .and_then(|text| write_text(BOOKMARKS_FILE, &text));
+        if let Err(e) = res {
+            warn!("bookmarks not saved: {}", e);
+        }
+    }
+}
+
+// bookmark being renamed and the name typed so far
+#[derive(Resource, Default)]
+pub struct BookmarkEdit {
+    index: Option<usize>,
+    text: String
+}
+
+#[derive(Component)]
+pub struct BookmarkPanel;
+
+#[derive(Component, Clone, Copy)]
+pub enum BookmarkButton {
+    Go(usize),
+    Rename(usize),
+    Delete(usize)
+}
+
+// ---
+
+fn startup(
+    mut cmd: Commands
+) {
+    cmd.spawn((
+        BookmarkPanel,
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.),
+            top: Val::Px(10.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0., 0., 0., 0.7)),
+        ZIndex(20),
+        Visibility::Hidden
+    ));
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn bookmark_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut bookmarks: ResMut<Bookmarks>,
+    panel_q: Single<&mut Visibility, With<BookmarkPanel>>,
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    julia_parent: Res<JuliaParent>,
+    cell: Res<PlayerCell>,
+    cam: Res<CamFollowParams>,
+    orbit: Res<ReferenceOrbit>,
+    palette: Res<Palette>
+) {
+    if keys.just_pressed(KeyCode::KeyL) {
+        let mut vis = panel_q.into_inner();
+        *vis = if *vis == Visibility::Visible {Visibility::Hidden} else {Visibility::Visible};
+    }
+    if keys.just_pressed(KeyCode::KeyK) {
+        // one sample per pixel is enough at this size
+        let (b_width, b_height) = (bounds.x.1 - bounds.x.0, bounds.y.1 - bounds.y.0);
+        let size = (THUMB_WIDTH, ((THUMB_WIDTH as f64 * b_height / b_width).round() as u32).max(1));
+        let view = FractalView::new(&formula, &bounds, &orbit);
+        let mut thumb = Vec::with_capacity((size.0 * size.1 * 4) as usize);
+        for j in 0 .. size.1 {
+            for i in 0 .. size.0 {
+                let x = bounds.x.0 + (i as f64 + 0.5) / size.0 as f64 * b_width;
+                let y = bounds.y.0 + (j as f64 + 0.5) / size.1 as f64 * b_height;
+                thumb.extend_from_slice(&palette.color(view.calc_color(x, y)).to_srgba().to_u8_array());
+            }
+        }
+        let name = format!("{} {}", formula.get().name(), bookmarks.0.len() + 1);
+        bookmarks.0.push(Bookmark {
+            name,
+            bounds: bounds.clone(),
+            formula: *formula,
+            julia_parent: julia_parent.0.clone(),
+            cell: (cell.0, cell.1),
+            camera: cam.clone(),
+            thumb_size: size,
+            thumb: encode_thumb(size, &thumb),
+            thumb_h: None
+        });
+        bookmarks.save();
+    }
+}
+
+// ---
+
+fn edit_name(
+    mut er: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut edit: ResMut<BookmarkEdit>,
+    mut bookmarks: ResMut<Bookmarks>
+) {
+    for e in er.read() {
+        if e.state != ButtonState::Pressed {
+            continue;
+        }
+        match &e.logical_key {
+            Key::Enter => {
+                if let Some(b) = edit.index.and_then(|i| bookmarks.0.get_mut(i)) {
+                    b.name = edit.text.trim().to_string();
+                }
+                edit.index = None;
+                bookmarks.save();
+                break;
+            },
+            Key::Escape => {
+                edit.index = None;
+                break;
+            },
+            Key::Backspace => {
+                edit.text.pop();
+            },
+            Key::Space => edit.text.push(' '),
+            Key::Character(s) => edit.text.push_str(s),
+            _ => ()
+        }
+    }
+    keys.reset_all();
+}
+
+// ---
+
+fn rebuild_panel(
+    mut cmd: Commands,
+    panel_q: Single<Entity, With<BookmarkPanel>>,
+    mut bookmarks: ResMut<Bookmarks>,
+    edit: Res<BookmarkEdit>,
+    mut images: ResMut<Assets<Image>>
+) {
+    let panel = panel_q.into_inner();
+    cmd.entity(panel).despawn_descendants();
+    // thumbnail handles are filled in here, that is not a change of the list
+    let bookmarks = bookmarks.bypass_change_detection();
+    cmd.entity(panel).with_children(|parent| {
+        parent.spawn((
+            Text::new("Bookmarks (K add, L hide)"),
+            TextFont {font_size: 14., ..default()}
+        ));
+        for (i, b) in bookmarks.0.iter_mut().enumerate() {
+            let thumb_h = b.thumb_h.get_or_insert_with(|| {
+                let mut image = Image::new_fill(
+                    Extent3d {width: b.thumb_size.0, height: b.thumb_size.1, depth_or_array_layers: 1},
+                    TextureDimension::D2,
+                    &Srgba::BLACK.to_u8_array(),
+                    TextureFormat::Rgba8UnormSrgb,
+                    RenderAssetUsages::all()
+                );
+                if let Some(pixels) = decode_thumb(&b.thumb).filter(|p| p.len() == image.data.len()) {
+                    image.data.copy_from_slice(&pixels);
+                }
+                images.add(image)
+            }).clone();
+            let name = if edit.index == Some(i) {format!("{}_", edit.text)} else {b.name.clone()};
+
+            parent.spawn(Node {
+                column_gap: Val::Px(6.),
+                align_items: AlignItems::Center,
+                ..default()
+            })
+            .with_children(|row| {
+                row.spawn((
+                    Node {
+                        width: Val::Px(b.thumb_size.0 as f32),
+                        height: Val::Px(b.thumb_size.1 as f32),
+                        ..default()
+                    },
+                    ImageNode::new(thumb_h)
+                ));
+                row.spawn((
+                    Node {width: Val::Px(160.), ..default()},
+                    Text::new(name),
+                    TextFont {font_size: 14., ..default()}
+                ));
+                for (label, button) in [("go", BookmarkButton::Go(i)), ("rename", BookmarkButton::Rename(i)), ("x", BookmarkButton::Delete(i))] {
+                    row.spawn((
+                        button,
+                        Node {
+                            padding: UiRect::axes(Val::Px(6.), Val::Px(2.)),
+                            border: UiRect::all(Val::Px(1.)),
+                            ..default()
+                        },
+                        BorderColor(Color::WHITE),
+                        Text::new(label),
+                        TextFont {font_size: 14., ..default()}
+                    ))
+                    .observe(on_button);
+                }
+            });
+        }
+    });
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn on_button(
+    click: Trigger<Pointer<Click>>,
+    button_q: Query<&BookmarkButton>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut edit: ResMut<BookmarkEdit>,
+    mut bounds: ResMut<FractallBounds>,
+    mut formula: ResMut<Formula>,
+    mut julia_parent: ResMut<JuliaParent>,
+    mut cell: ResMut<PlayerCell>,
+    mut cam: ResMut<CamFollowParams>,
+    p_q: Single<(&mut Transform, &mut LinearVelocity), With<Player>>,
+    mut next: ResMut<NextState<GameState>>,
+    mut rw: EventWriter<RecordView>
+) {
+    let Ok(button) = button_q.get(click.entity()) else {
+        return;
+    };
+    match *button {
+        BookmarkButton::Go(i) => {
+            let Some(b) = bookmarks.0.get(i) else {
+                return;
+            };
+            // a new bounds value forces the valley to rebuild, which also drops the player on the ground
+            *bounds = b.bounds.clone();
+            *formula = b.formula;
+            julia_parent.0 = b.julia_parent.clone();
+            cell.0 = b.cell.0;
+            cell.1 = b.cell.1;
+            *cam = b.camera.clone();
+            let (mut t, mut lv) = p_q.into_inner();
+            let pos = cell2xz(b.cell);
+            t.translation.x = pos.x;
+            t.translation.z = pos.z;
+            lv.0 = Vec3::ZERO;
+            next.set(GameState::Game);
+            rw.send_default();
+        },
+        BookmarkButton::Rename(i) => {
+            edit.index = Some(i);
+            edit.text = bookmarks.0[i].name.clone();
+        },
+        BookmarkButton::Delete(i) => {
+            bookmarks.0.remove(i);
+            edit.index = None;
+            bookmarks.save();
+        }
+    }
+}
+
+// ---
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_thumb(size: (u32, u32), rgba: &[u8]) -> String {
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, size.0, size.1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let res = encoder.write_header().and_then(|mut w| w.write_image_data(rgba));
+    if res.is_err() {
+        return String::new();
+    }
+
+    let mut text = String::with_capacity(png_data.len().div_ceil(3) * 4);
+    for chunk in png_data.chunks(3) {
+        let b = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0 .. 4 {
+            text.push(if i <= chunk.len() {BASE64[(n >> (18 - 6 * i) & 63) as usize] as char} else {'='});
+        }
+    }
+    text
+}
+
+// rgba pixels, None for a missing or broken thumbnail
+fn decode_thumb(text: &str) -> Option<Vec<u8>> {
+    let mut png_data = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in text.bytes().take_while(|c| *c != b'=') {
+        n = n << 6 | BASE64.iter().position(|b| *b == c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            png_data.push((n >> bits) as u8);
+        }
+    }
+
+    let mut reader = png::Decoder::new(png_data.as_slice()).read_info().ok()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).ok()?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return None;
+    }
+    pixels.truncate(info.buffer_size());
+    Some(pixels)
+}
+
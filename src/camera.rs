@@ -7,6 +7,7 @@ use bevy::{
 };
 use avian3d::schedule::PhysicsSet;
 use bevy::core_pipeline::Skybox;
+use serde::{Deserialize, Serialize};
 use crate::shared::{cell2xz, Focus, PLAYER_START_CELL};
 use crate::GameState;
 
@@ -26,7 +27,7 @@ impl Plugin for CameraPlugin {
             .run_if(in_state(GameState::Game))
             .run_if(on_event::<MouseMotion>)
         )
-        .add_systems(Update, distancing.run_if(on_event::<MouseWheel>))    
+        .add_systems(Update, distancing.run_if(on_event::<MouseWheel>).run_if(in_state(GameState::Game)))    
         .add_observer(cam_reset)
         ; 
     }
@@ -37,7 +38,7 @@ impl Plugin for CameraPlugin {
 #[derive(Component)]
 pub struct Cam;
 
-#[derive(Resource)]
+#[derive(Resource, Clone, Serialize, Deserialize)]
 pub struct CamFollowParams {
     pub tranlation_bias: Vec3,
     pub look_bias: Vec3,
@@ -56,6 +57,11 @@ fn setup (
 ) {
     cmd.spawn((
         Camera3d::default(),
+        // far enough for the LOD rings
+        Projection::Perspective(PerspectiveProjection {
+            far: 20000.,
+            ..default()
+        }),
         Transform::from_translation(cell2xz(PLAYER_START_CELL)),
         Cam,
         Name::new("Camera"),
@@ -0,0 +1,359 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use num_bigint::BigInt;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::formula::{distance_estimate, escape_value, Formula, SMOOTH_BAILOUT};
+
+// below this view width f64 runs out of digits and the perturbation path takes over
+pub const DEEP_WIDTH: f64 = 1e-9;
+
+// ---
+
+// fixed point number: mant / 2^prec
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BigFixed {
+    mant: BigInt,
+    prec: u32
+}
+
+pub type BigComplex = (BigFixed, BigFixed);
+
+impl BigFixed {
+    pub fn from_f64(v: f64, prec: u32) -> Self {
+        if v == 0. || !v.is_finite() {
+            return Self{mant: BigInt::ZERO, prec};
+        }
+        let bits = v.to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as i64;
+        let frac = bits & 0xf_ffff_ffff_ffff;
+        let (m, exp) = if exp == 0 {(frac << 1, exp - 1075)} else {(frac | 0x10_0000_0000_0000, exp - 1075)};
+        let shift = exp + prec as i64;
+        let mut mant = BigInt::from(m);
+        mant = if shift >= 0 {mant << shift as u64} else {mant >> (-shift) as u64};
+        if v < 0. {
+            mant = -mant;
+        }
+        Self{mant, prec}
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let bits = self.mant.bits();
+        let (top, shift) = if bits > 64 {
+            let shift = bits - 64;
+            (&self.mant >> shift, shift as i32)
+        } else {
+            (self.mant.clone(), 0)
+        };
+        let (sign, mag) = top.to_u64_digits();
+        let v = mag.first().copied().unwrap_or(0) as f64 * 2f64.powi(shift - self.prec as i32);
+        if sign == num_bigint::Sign::Minus {-v} else {v}
+    }
+
+    pub fn prec(&self) -> u32 {
+        self.prec
+    }
+
+    pub fn with_prec(&self, prec: u32) -> Self {
+        let mant = if prec >= self.prec {
+            &self.mant << (prec - self.prec)
+        } else {
+            &self.mant >> (self.prec - prec)
+        };
+        Self{mant, prec}
+    }
+
+    pub fn add(&self, o: &Self) -> Self {
+        let prec = self.prec.max(o.prec);
+        Self{mant: self.with_prec(prec).mant + o.with_prec(prec).mant, prec}
+    }
+
+    pub fn sub(&self, o: &Self) -> Self {
+        let prec = self.prec.max(o.prec);
+        Self{mant: self.with_prec(prec).mant - o.with_prec(prec).mant, prec}
+    }
+
+    pub fn mul(&self, o: &Self) -> Self {
+        let prec = self.prec.max(o.prec);
+        Self{mant: (self.with_prec(prec).mant * o.with_prec(prec).mant) >> prec, prec}
+    }
+
+    // decimal text with the given number of fraction digits, truncated
+    pub fn to_decimal(&self, digits: usize) -> String {
+        let mag = self.mant.magnitude();
+        let int = mag >> self.prec;
+        let mut frac = mag - (&int << self.prec);
+        let sign = if self.mant.sign() == num_bigint::Sign::Minus {"-"} else {""};
+        let mut s = format!("{}{}.", sign, int);
+        for _ in 0 .. digits {
+            frac *= 10u32;
+            let d = &frac >> self.prec;
+            frac -= &d << self.prec;
+            s += &d.to_string();
+        }
+        s
+    }
+
+    pub fn abs(&self) -> Self {
+        Self{mant: BigInt::from(self.mant.magnitude().clone()), prec: self.prec}
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mant == BigInt::ZERO
+    }
+}
+
+// exact text form, mantissa and binary exponent like 123p-64
+impl std::fmt::Display for BigFixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}p-{}", self.mant, self.prec)
+    }
+}
+
+impl std::str::FromStr for BigFixed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mant, prec) = s.split_once("p-").ok_or_else(|| format!("bad fixed point number {}", s))?;
+        Ok(Self {
+            mant: mant.parse().map_err(|e| format!("{}: {}", s, e))?,
+            prec: prec.parse().map_err(|e| format!("{}: {}", s, e))?
+        })
+    }
+}
+
+// stored as the exact text form, f64 would lose the deep zoom origin
+impl Serialize for BigFixed {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BigFixed {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?.parse().map_err(de::Error::custom)
+    }
+}
+
+// ---
+
+// enough fractional bits to resolve a pixel of a view this wide
+pub fn prec_for_width(width: f64) -> u32 {
+    64 + (-width.log2()).max(0.).ceil() as u32
+}
+
+// ---
+
+// reference orbit of the view centre, computed in high precision and stored in f64
+#[derive(Debug)]
+pub struct Orbit {
+    // relative to the bounds origin
+    pub center: (f64, f64),
+    pub z: Vec<(f64, f64)>
+}
+
+#[derive(Resource, Debug, Default, Clone)]
+pub struct ReferenceOrbit(pub Option<Arc<Orbit>>);
+
+impl Orbit {
+    pub fn compute(formula: &Formula, origin: &BigComplex, center: (f64, f64), prec: u32) -> Option<Self> {
+        let point = (
+            origin.0.add(&BigFixed::from_f64(center.0, prec)),
+            origin.1.add(&BigFixed::from_f64(center.1, prec))
+        );
+        let (mut z, c) = match formula.julia {
+            Some(c) => (point, (BigFixed::from_f64(c.0, prec), BigFixed::from_f64(c.1, prec))),
+            None => ((BigFixed::from_f64(0., prec), BigFixed::from_f64(0., prec)), point)
+        };
+        let f = formula.get();
+        let mut orbit = Vec::with_capacity(formula.max_iter + 2);
+        loop {
+            let zf = (z.0.to_f64(), z.1.to_f64());
+            orbit.push(zf);
+            if zf.0 * zf.0 + zf.1 * zf.1 >= SMOOTH_BAILOUT || orbit.len() > formula.max_iter + 1 {
+                break;
+            }
+            z = f.step_deep(&z, &c)?;
+        }
+        Some(Self{center, z: orbit})
+    }
+
+    // perturbed iteration, dc is the offset of the point from the reference, gives the escape count,
+    // the step it passed 2, the last z and its derivative by the start point when asked for
+    fn iterate(&self, formula: &Formula, dc: (f64, f64), derivative: bool) -> (usize, Option<usize>, (f64, f64), (f64, f64)) {
+        let f = formula.get();
+        let bailout = if formula.smooth || derivative {SMOOTH_BAILOUT} else {4.0};
+        // the Mandelbrot orbit starts from 0, one step before z = c
+        let (mut dz, dc, skip, mut der) = match formula.julia {
+            Some(_) => (dc, (0., 0.), 0, (1., 0.)),
+            None => ((0., 0.), dc, 1, (0., 0.))
+        };
+        let z0 = self.z[0];
+        let last = self.z.len() - 1;
+        let mut m = 0;
+        let mut n: usize = 0;
+        let mut first = None;
+        let mut z;
+        loop {
+            let zr = self.z[m];
+            z = (zr.0 + dz.0, zr.1 + dz.1);
+            let r2 = z.0 * z.0 + z.1 * z.1;
+            if first.is_none() && r2 >= 4. {
+                first = Some(n.saturating_sub(skip));
+            }
+            if r2 >= bailout || n >= formula.max_iter + skip {
+                break;
+            }
+            if derivative {
+                der = f.step_dz(z, der);
+                if skip == 1 {
+                    der.0 += 1.;
+                }
+            }
+            // rebase to the start of the orbit when the point gets closer to it than to the reference,
+            // this also covers the reference escaping before the point does
+            let back = (z.0 - z0.0, z.1 - z0.1);
+            let zr = if m == last || back.0 * back.0 + back.1 * back.1 < dz.0 * dz.0 + dz.1 * dz.1 {
+                dz = back;
+                m = 0;
+                z0
+            } else {
+                zr
+            };
+            dz = f.perturb(zr, dz, dc);
+            m += 1;
+            n += 1;
+        }
+        (n.saturating_sub(skip), first, z, der)
+    }
+
+    // None when the point doesn't escape within max_iter
+    pub fn calc_color(&self, formula: &Formula, dc: (f64, f64)) -> Option<f32> {
+        let (n, first, z, _) = self.iterate(formula, dc, false);
+        escape_value(n, first, z, formula.get().degree(), formula.max_iter, formula.smooth)
+    }
+
+    // value and distance estimate from the same orbit
+    pub fn calc_color_distance(&self, formula: &Formula, dc: (f64, f64)) -> (Option<f32>, f64) {
+        let (n, first, z, der) = self.iterate(formula, dc, true);
+        let distance = if first.is_some() {distance_estimate(z, der)} else {0.};
+        (escape_value(n, first, z, formula.get().degree(), formula.max_iter, formula.smooth), distance)
+    }
+}
+
+// ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(c: (f64, f64), prec: u32) -> BigComplex {
+        (BigFixed::from_f64(c.0, prec), BigFixed::from_f64(c.1, prec))
+    }
+
+    #[test]
+    fn fixed_round_trip() {
+        for v in [0., 1., -1., 1.5, -0.75, 0.1, 1e-20, -2.5e-30, 1234.5678] {
+            assert_eq!(BigFixed::from_f64(v, 160).to_f64(), v);
+        }
+        let a = BigFixed::from_f64(-0.123456789, 128);
+        assert_eq!(a.to_string().parse::<BigFixed>().unwrap(), a);
+        assert_eq!(a.with_prec(200).with_prec(128), a);
+    }
+
+    #[test]
+    fn fixed_arithmetic() {
+        let prec = 128;
+        let (a, b) = (BigFixed::from_f64(1.25, prec), BigFixed::from_f64(-0.375, prec));
+        assert_eq!(a.add(&b).to_f64(), 0.875);
+        assert_eq!(a.sub(&b).to_f64(), 1.625);
+        assert_eq!(a.mul(&b).to_f64(), -0.46875);
+        assert!(a.sub(&a).is_zero());
+        assert_eq!(b.abs().to_f64(), 0.375);
+        assert_eq!(a.abs(), a);
+        // digits below f64 survive the round trip through the sum
+        let tiny = BigFixed::from_f64(1e-30, prec);
+        assert_eq!(a.add(&tiny).sub(&a).to_f64(), tiny.to_f64());
+    }
+
+    #[test]
+    fn precision_follows_width() {
+        assert_eq!(prec_for_width(4.), 64);
+        assert_eq!(prec_for_width(1.), 64);
+        assert!(prec_for_width(1e-30) >= 64 + 99);
+        assert!(prec_for_width(1e-12) < prec_for_width(1e-13));
+    }
+
+    // perturbed values match the direct f64 ones where f64 still has digits to spare
+    fn assert_matches(formula: &Formula, reference: (f64, f64), points: &[(f64, f64)]) {
+        let orbit = Orbit::compute(formula, &big((0., 0.), 128), reference, 128).unwrap();
+        for &c in points {
+            let dc = (c.0 - reference.0, c.1 - reference.1);
+            let (direct, perturbed) = (formula.escape(c.0, c.1), orbit.calc_color(formula, dc));
+            let same = match (direct, perturbed) {
+                (Some(a), Some(b)) => (a - b).abs() < 1e-3,
+                (a, b) => a == b
+            };
+            assert!(same, "{:?}: direct {:?} perturbed {:?}", c, direct, perturbed);
+        }
+    }
+
+    #[test]
+    fn perturbation_matches_direct() {
+        let reference = (-0.7436, 0.1318);
+        let points: Vec<_> = (0 .. 8).map(|i| (reference.0 + i as f64 * 1e-4, reference.1 - i as f64 * 7e-5)).collect();
+        for smooth in [false, true] {
+            let formula = Formula{smooth, ..default()};
+            assert_matches(&formula, reference, &points);
+        }
+    }
+
+    // the abs folding formulas perturb with their own case split, every formula has a deep orbit
+    #[test]
+    fn perturbation_matches_direct_all_formulas() {
+        let references = [(-0.7436, 0.1318), (-1.762, -0.028), (-1.387, 0.017), (-0.657, 0.497), (-1.767, 0.027)];
+        for (index, reference) in references.into_iter().enumerate() {
+            let points: Vec<_> = (0 .. 8).map(|i| (reference.0 + i as f64 * 1e-4, reference.1 - i as f64 * 7e-5)).collect();
+            for smooth in [false, true] {
+                assert_matches(&Formula{index, smooth, ..default()}, reference, &points);
+            }
+        }
+    }
+
+    #[test]
+    fn perturbation_matches_direct_julia() {
+        let reference = (0.1, 0.05);
+        let points: Vec<_> = (0 .. 8).map(|i| (reference.0 + i as f64 * 1e-3, reference.1 + i as f64 * 2e-3)).collect();
+        for index in 0 .. 5 {
+            assert_matches(&Formula{index, smooth: true, julia: Some((-0.8, 0.156)), ..default()}, reference, &points);
+        }
+    }
+
+    #[test]
+    fn one_pass_distance() {
+        let reference = (-0.7436, 0.1318);
+        for smooth in [false, true] {
+            let formula = Formula{smooth, ..default()};
+            let orbit = Orbit::compute(&formula, &big((0., 0.), 128), reference, 128).unwrap();
+            for i in 0 .. 8 {
+                let c = (reference.0 + i as f64 * 1e-4, reference.1 - i as f64 * 7e-5);
+                let (value, distance) = formula.calc_color_distance(c.0, c.1);
+                assert_eq!(value, formula.escape(c.0, c.1).unwrap_or(0.));
+                let (pv, pd) = orbit.calc_color_distance(&formula, (c.0 - reference.0, c.1 - reference.1));
+                let pv = pv.unwrap_or(0.);
+                assert!((value - pv).abs() < 1e-3, "{:?}: direct {} perturbed {}", c, value, pv);
+                assert!((distance - pd).abs() <= distance * 1e-3, "{:?}: direct {} perturbed {}", c, distance, pd);
+            }
+        }
+    }
+
+    #[test]
+    fn perturbation_rebases() {
+        let formula = Formula{smooth: true, ..default()};
+        // reference inside the set, the points wander far off its orbit and escape
+        assert_matches(&formula, (-0.1, 0.1), &[(0.4, 0.7), (-1.9, 0.3), (0.3, -0.5)]);
+        // reference escaping early, a point inside the set has to outlive it
+        assert_matches(&formula, (0.3, 0.5), &[(-0.1, 0.1), (-1., 0.05), (0.31, 0.52)]);
+    }
+}
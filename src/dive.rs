@@ -0,0 +1,147 @@
+use avian3d::prelude::LinearVelocity;
+use bevy::{input::keyboard::KeyboardInput, prelude::*};
+
+use crate::{
+    formula::Formula,
+    fractal::{rebase_origin, FractallBounds, FractallCollors},
+    heights::HeightMap,
+    player::{AdjustY, Player, PlayerCell},
+    shared::TilesCenter,
+    terrace::{TerraceBlocks, TerraceMorph},
+    GameState
+};
+
+pub struct DivePlugin;
+impl Plugin for DivePlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<DiveSettings>()
+        .init_resource::<Dive>()
+        .add_systems(Update, dive_keys.run_if(in_state(GameState::Game)).run_if(on_event::<KeyboardInput>))
+        .add_systems(Update, dive.after(dive_keys).before(rebase_origin).run_if(|d: Res<Dive>| d.active()))
+        .add_systems(PostUpdate, morph)
+        ;
+    }
+}
+
+// ---
+
+const DIVE_FACTORS: [f64; 4] = [2., 4., 8., 16.];
+// seconds the terraces take to move from the old heights to the new ones
+const DIVE_SECS: f64 = 1.5;
+
+#[derive(Resource, Debug)]
+pub struct DiveSettings {
+    pub factor: f64
+}
+
+impl Default for DiveSettings {
+    fn default() -> Self {
+        Self {factor: 4.}
+    }
+}
+
+// seconds into the dive, None when there is none
+#[derive(Resource, Debug, Default)]
+pub struct Dive {
+    elapsed: Option<f64>
+}
+
+impl Dive {
+    pub fn active(&self) -> bool {
+        self.elapsed.is_some()
+    }
+
+    // share of the height change still to go, eased at both ends
+    pub fn remaining(&self) -> f32 {
+        let Some(elapsed) = self.elapsed else {
+            return 0.;
+        };
+        let t = (elapsed / DIVE_SECS).clamp(0., 1.) as f32;
+        1. - t * t * (3. - 2. * t)
+    }
+}
+
+// ---
+
+fn dive_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<DiveSettings>,
+    mut dive: ResMut<Dive>,
+    mut bounds: ResMut<FractallBounds>,
+    formula: Res<Formula>,
+    cell: Res<PlayerCell>
+) {
+    if !keys.just_pressed(KeyCode::KeyZ) {
+        return;
+    }
+    if keys.pressed(KeyCode::ControlLeft) {
+        let i = DIVE_FACTORS.iter().position(|f| *f == settings.factor).unwrap_or(0);
+        settings.factor = DIVE_FACTORS[(i + 1) % DIVE_FACTORS.len()];
+        return;
+    }
+    if dive.active() {
+        return;
+    }
+    let zoom = if keys.pressed(KeyCode::ShiftLeft) {
+        // no higher than the initial view of the formula
+        let initial = formula.bounds();
+        let top = (initial.0.1 - initial.0.0) / (bounds.x.1 - bounds.x.0);
+        settings.factor.min(top).max(1.)
+    } else {
+        1. / settings.factor
+    };
+    if zoom == 1. {
+        return;
+    }
+    // one recompute, the player cell keeps its complex point so the player stands on the matching cell of the new grid
+    let p = bounds.cell_point((cell.0, cell.1));
+    bounds.zoom_at(p, zoom);
+    dive.elapsed = Some(0.);
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn dive(
+    mut dive: ResMut<Dive>,
+    colors: Res<FractallCollors>,
+    heights: Res<HeightMap>,
+    formula: Res<Formula>,
+    tc: Res<TilesCenter>,
+    p_q: Single<&mut LinearVelocity, With<Player>>,
+    time: Res<Time>,
+    mut cmd: Commands
+) {
+    // no fall speed may build up while the terraces move under the player
+    p_q.into_inner().0.y = 0.;
+    let Some(elapsed) = dive.elapsed.as_mut() else {
+        return;
+    };
+    *elapsed += time.delta_secs_f64();
+    if *elapsed < DIVE_SECS {
+        return;
+    }
+    dive.elapsed = None;
+    cmd.trigger(AdjustY(heights.cell_top(&colors, (tc.0, tc.1), formula.max_iter) + 2.));
+}
+
+// ---
+
+// the rebuilt chunks and rings start at the old heights, this eases them to the new ones
+fn morph(
+    dive: Res<Dive>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut morph_q: Query<(Entity, &Mesh3d, &TerraceBlocks, &mut TerraceMorph)>,
+    mut cmd: Commands
+) {
+    let factor = dive.remaining();
+    for (e, mesh3d, blocks, mut morph) in &mut morph_q {
+        if let Some(mesh) = meshes.get_mut(&mesh3d.0) {
+            blocks.raise(mesh, &mut morph, factor);
+        }
+        if factor == 0. {
+            cmd.entity(e).remove::<TerraceMorph>();
+        }
+    }
+}
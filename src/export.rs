@@ -0,0 +1,444 @@
+use std::{fs, io, time::{SystemTime, UNIX_EPOCH}};
+
+use bevy::{
+    input::keyboard::KeyboardInput,
+    prelude::*,
+    tasks::AsyncComputeTaskPool,
+    utils::HashMap
+};
+
+use crate::{
+    deep::ReferenceOrbit,
+    formula::Formula,
+    fractal::{FractalView, FractallBounds, FractallCollors},
+    heights::HeightMap,
+    palette::Palette,
+    shared::{CELL_HEIGHT, CELL_SIZE, VALLEY_SIZE}
+};
+
+pub struct ExportPlugin;
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<ExportSettings>()
+        .add_event::<ExportRequest>()
+        .add_systems(Update, export_keys.run_if(on_event::<KeyboardInput>).before(export))
+        .add_systems(Update, export.run_if(on_event::<ExportRequest>))
+        ;
+    }
+}
+
+// ---
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Gltf,
+    Obj,
+    Stl
+}
+
+impl ExportFormat {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Gltf => Self::Obj,
+            Self::Obj => Self::Stl,
+            Self::Stl => Self::Gltf
+        }
+    }
+
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Gltf => "glb",
+            Self::Obj => "obj",
+            Self::Stl => "stl"
+        }
+    }
+}
+
+// vertical_scale 1 keeps the in-game proportions of the height mapping
+#[derive(Resource, Debug)]
+pub struct ExportSettings {
+    pub format: ExportFormat,
+    pub vertical_scale: f32
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {format: ExportFormat::Gltf, vertical_scale: 1.}
+    }
+}
+
+type CellRange = ((i32, i32), (i32, i32));
+
+// a larger region would take minutes and gigabytes as a solid
+const MAX_REGION_CELLS: i32 = 1024 * 1024;
+
+// region is a cell rectangle of the valley, None takes the walkable window
+#[derive(Event, Debug, Default)]
+pub struct ExportRequest {
+    pub region: Option<CellRange>
+}
+
+// ---
+
+fn export_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<ExportSettings>,
+    mut ew: EventWriter<ExportRequest>
+) {
+    if keys.just_pressed(KeyCode::KeyX) {
+        if keys.pressed(KeyCode::ShiftLeft) {
+            settings.format = settings.format.next();
+            info!("export format: {}", settings.format.ext());
+        } else {
+            ew.send_default();
+        }
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        settings.vertical_scale *= 2.;
+        info!("export vertical scale: {}", settings.vertical_scale);
+    }
+    if keys.just_pressed(KeyCode::Comma) {
+        settings.vertical_scale *= 0.5;
+        info!("export vertical scale: {}", settings.vertical_scale);
+    }
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn export(
+    mut er: EventReader<ExportRequest>,
+    settings: Res<ExportSettings>,
+    colors: Res<FractallCollors>,
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    orbit: Res<ReferenceOrbit>,
+    palette: Res<Palette>,
+    heights: Res<HeightMap>
+) {
+    for req in er.read() {
+        // the browser has no file system to write into
+        if cfg!(target_arch = "wasm32") {
+            warn!("export is not available in the browser");
+            continue;
+        }
+        // iteration value and distance in cells, the region ones are sampled on the task
+        let (region, samples) = match req.region {
+            Some(r) if (r.0.1 - r.0.0) * (r.1.1 - r.1.0) > MAX_REGION_CELLS => {
+                warn!("export region of {} x {} cells is too large", r.0.1 - r.0.0, r.1.1 - r.1.0);
+                continue;
+            },
+            Some(r) => (r, None),
+            None => {
+                let Some(r) = colors.range() else {
+                    continue;
+                };
+                (r, Some(cells(r).map(|c| (colors.get(c), colors.distance(c))).collect::<Vec<(f32, f32)>>()))
+            }
+        };
+        let view = FractalView::new(&formula, &bounds, &orbit);
+        let bounds = bounds.clone();
+        let (palette, heights) = (palette.clone(), heights.clone());
+        let (format, vertical_scale, max_iter) = (settings.format, settings.vertical_scale, formula.max_iter);
+        info!("export of {} x {} cells started", region.0.1 - region.0.0, region.1.1 - region.1.0);
+        // a large region takes seconds to sample and triangulate, keep it off the main thread
+        AsyncComputeTaskPool::get().spawn(async move {
+            let samples = samples.unwrap_or_else(|| {
+                let cell_width = (bounds.x.1 - bounds.x.0) / VALLEY_SIZE as f64;
+                cells(region).map(|c| {
+                    let p = bounds.cell_point(c);
+                    if heights.mode.needs_distance() {
+                        let (value, distance) = view.calc_color_distance(p.0, p.1);
+                        (value, (distance / cell_width) as f32)
+                    } else {
+                        (view.calc_color(p.0, p.1), 0.)
+                    }
+                }).collect()
+            });
+            let values: Vec<f32> = samples.iter().map(|s| s.0).collect();
+            let levels: Vec<f32> = samples.iter().map(|s| heights.units(s.0, s.1, max_iter)).collect();
+
+            let solid = Solid::build(region, &values, &levels, vertical_scale * heights.scale(), &palette);
+            let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let name = format!("valley-{}", stamp);
+            let res = match format {
+                ExportFormat::Gltf => fs::write(format!("{}.glb", name), solid.to_glb()),
+                ExportFormat::Obj => solid.write_obj(&name),
+                ExportFormat::Stl => fs::write(format!("{}.stl", name), solid.to_stl())
+            };
+            match res {
+                Ok(_) => info!("exported {}.{}", name, format.ext()),
+                Err(e) => warn!("export failed: {}", e)
+            }
+        }).detach();
+    }
+}
+
+// ---
+
+fn cells(r: CellRange) -> impl Iterator<Item = (i32, i32)> {
+    (r.0.0 .. r.0.1).flat_map(move |x| (r.1.0 .. r.1.1).map(move |z| (x, z)))
+}
+
+// ---
+
+// closed stepped solid, vertices are shared so every edge has its opposite and the surface stays watertight
+struct Solid {
+    positions: Vec<[f32; 3]>,
+    lookup: HashMap<[u32; 3], u32>,
+    // triangles per material, the last material is the base
+    groups: Vec<Vec<[u32; 3]>>,
+    materials: Vec<[f32; 4]>
+}
+
+impl Solid {
+    // levels are the height mapping units of the cells, values pick the materials
+    fn build(region: CellRange, values: &[f32], levels: &[f32], vertical_scale: f32, palette: &Palette) -> Self {
+        let w = (region.0.1 - region.0.0) as usize;
+        let d = (region.1.1 - region.1.0) as usize;
+        let step = CELL_HEIGHT * vertical_scale;
+        let heights: Vec<f32> = levels.iter().map(|v| v * step).collect();
+        let base = heights.iter().copied().fold(f32::MAX, f32::min) - step;
+
+        let mut materials: Vec<[f32; 4]> = (0 .. palette.bands())
+            .map(|band| LinearRgba::from(palette.band_color(band).with_alpha(1.)).to_f32_array())
+            .collect();
+        materials.push([0.2, 0.2, 0.2, 1.]);
+        let base_mat = materials.len() - 1;
+        let cell_mat: Vec<usize> = values.iter()
+            .map(|v| palette.band(*v))
+            .collect();
+
+        let mut s = Self {
+            positions: Vec::new(),
+            lookup: HashMap::new(),
+            groups: vec![Vec::new(); materials.len()],
+            materials
+        };
+
+        let h = |i: usize, j: usize| heights[i * d + j];
+        let mat = |i: usize, j: usize| cell_mat[i * d + j];
+
+        // every height meeting at a grid corner, walls are split there so no edge gets a t-junction
+        let corner = |i: usize, j: usize| {
+            let mut hs = Vec::new();
+            for (ci, cj) in [(i.wrapping_sub(1), j.wrapping_sub(1)), (i, j.wrapping_sub(1)), (i.wrapping_sub(1), j), (i, j)] {
+                if ci < w && cj < d {
+                    hs.push(h(ci, cj));
+                }
+            }
+            if i == 0 || j == 0 || i == w || j == d {
+                hs.push(base);
+            }
+            hs.sort_by(f32::total_cmp);
+            hs.dedup();
+            hs
+        };
+        let xz = |i: usize, j: usize| Vec2::new(i as f32 * CELL_SIZE, j as f32 * CELL_SIZE);
+
+        for i in 0 .. w {
+            for j in 0 .. d {
+                let (p0, p1) = (xz(i, j), xz(i + 1, j + 1));
+                let y = h(i, j);
+                s.quad(
+                    [Vec3::new(p0.x, y, p0.y), Vec3::new(p0.x, y, p1.y), Vec3::new(p1.x, y, p1.y), Vec3::new(p1.x, y, p0.y)],
+                    mat(i, j)
+                );
+                s.quad(
+                    [Vec3::new(p0.x, base, p0.y), Vec3::new(p1.x, base, p0.y), Vec3::new(p1.x, base, p1.y), Vec3::new(p0.x, base, p1.y)],
+                    base_mat
+                );
+            }
+        }
+
+        // walls on x = i lines
+        for i in 0 ..= w {
+            for j in 0 .. d {
+                let (lo, hi, normal, m) = match (i.checked_sub(1), (i < w).then_some(i)) {
+                    (Some(a), Some(b)) => {
+                        let (ha, hb) = (h(a, j), h(b, j));
+                        if ha == hb {
+                            continue;
+                        }
+                        if ha > hb {(hb, ha, Vec2::X, mat(a, j))} else {(ha, hb, Vec2::NEG_X, mat(b, j))}
+                    },
+                    (Some(a), None) => (base, h(a, j), Vec2::X, mat(a, j)),
+                    (None, Some(b)) => (base, h(b, j), Vec2::NEG_X, mat(b, j)),
+                    (None, None) => continue
+                };
+                s.wall((xz(i, j), corner(i, j)), (xz(i, j + 1), corner(i, j + 1)), lo, hi, normal, m);
+            }
+        }
+
+        // walls on z = j lines
+        for j in 0 ..= d {
+            for i in 0 .. w {
+                let (lo, hi, normal, m) = match (j.checked_sub(1), (j < d).then_some(j)) {
+                    (Some(a), Some(b)) => {
+                        let (ha, hb) = (h(i, a), h(i, b));
+                        if ha == hb {
+                            continue;
+                        }
+                        if ha > hb {(hb, ha, Vec2::Y, mat(i, a))} else {(ha, hb, Vec2::NEG_Y, mat(i, b))}
+                    },
+                    (Some(a), None) => (base, h(i, a), Vec2::Y, mat(i, a)),
+                    (None, Some(b)) => (base, h(i, b), Vec2::NEG_Y, mat(i, b)),
+                    (None, None) => continue
+                };
+                s.wall((xz(i, j), corner(i, j)), (xz(i + 1, j), corner(i + 1, j)), lo, hi, normal, m);
+            }
+        }
+
+        s
+    }
+
+    fn vertex(&mut self, p: Vec3) -> u32 {
+        let key = p.to_array().map(f32::to_bits);
+        *self.lookup.entry(key).or_insert_with(|| {
+            self.positions.push(p.to_array());
+            self.positions.len() as u32 - 1
+        })
+    }
+
+    // corners go counter clockwise as seen from outside
+    fn quad(&mut self, corners: [Vec3; 4], m: usize) {
+        let [a, b, c, d] = corners.map(|p| self.vertex(p));
+        self.groups[m].extend([[a, b, c], [a, c, d]]);
+    }
+
+    // vertical wall between two corner lines facing normal (xz), laddered through every corner height
+    fn wall(&mut self, a: (Vec2, Vec<f32>), b: (Vec2, Vec<f32>), lo: f32, hi: f32, normal: Vec2, m: usize) {
+        // p -> q runs so that (q - p) x up is the normal
+        let dir = Vec2::new(normal.y, -normal.x);
+        let (p, q) = if (b.0 - a.0).dot(dir) > 0. {(a, b)} else {(b, a)};
+        let chain = |c: &(Vec2, Vec<f32>)| -> Vec<Vec3> {
+            c.1.iter().filter(|y| **y >= lo && **y <= hi).map(|y| Vec3::new(c.0.x, *y, c.0.y)).collect()
+        };
+        let (cp, cq) = (chain(&p), chain(&q));
+        let (mut i, mut j) = (0, 0);
+        while i + 1 < cp.len() || j + 1 < cq.len() {
+            let step_q = i + 1 >= cp.len() || (j + 1 < cq.len() && cq[j + 1].y <= cp[i + 1].y);
+            let (vp, vq) = (self.vertex(cp[i]), self.vertex(cq[j]));
+            if step_q {
+                let next = self.vertex(cq[j + 1]);
+                self.groups[m].push([vp, vq, next]);
+                j += 1;
+            } else {
+                let next = self.vertex(cp[i + 1]);
+                self.groups[m].push([vp, vq, next]);
+                i += 1;
+            }
+        }
+    }
+
+    fn triangles(&self) -> impl Iterator<Item = &[u32; 3]> {
+        self.groups.iter().flatten()
+    }
+
+    // ---
+
+    fn write_obj(&self, name: &str) -> io::Result<()> {
+        let mut mtl = String::new();
+        let mut obj = format!("mtllib {}.mtl\n", name);
+        for p in &self.positions {
+            obj += &format!("v {} {} {}\n", p[0], p[1], p[2]);
+        }
+        for (m, tris) in self.groups.iter().enumerate().filter(|(_, t)| !t.is_empty()) {
+            let c = Srgba::from(LinearRgba::from_f32_array(self.materials[m]));
+            mtl += &format!("newmtl c{}\nKd {} {} {}\n\n", m, c.red, c.green, c.blue);
+            obj += &format!("usemtl c{}\n", m);
+            for t in tris {
+                obj += &format!("f {} {} {}\n", t[0] + 1, t[1] + 1, t[2] + 1);
+            }
+        }
+        fs::write(format!("{}.mtl", name), mtl)?;
+        fs::write(format!("{}.obj", name), obj)
+    }
+
+    // stl is z up and has no colours
+    fn to_stl(&self) -> Vec<u8> {
+        let zup = |p: [f32; 3]| Vec3::new(p[0], -p[2], p[1]);
+        let count = self.triangles().count();
+        let mut out = vec![0u8; 80];
+        out.extend((count as u32).to_le_bytes());
+        for t in self.triangles() {
+            let [a, b, c] = t.map(|i| zup(self.positions[i as usize]));
+            let n = (b - a).cross(c - a).normalize_or_zero();
+            for v in [n, a, b, c] {
+                for f in v.to_array() {
+                    out.extend(f.to_le_bytes());
+                }
+            }
+            out.extend(0u16.to_le_bytes());
+        }
+        out
+    }
+
+    // binary gltf, one primitive per colour sharing the position accessor
+    fn to_glb(&self) -> Vec<u8> {
+        let mut bin: Vec<u8> = Vec::new();
+        let (mut min, mut max) = (Vec3::MAX, Vec3::MIN);
+        for p in &self.positions {
+            let v = Vec3::from_array(*p);
+            min = min.min(v);
+            max = max.max(v);
+            for f in p {
+                bin.extend(f.to_le_bytes());
+            }
+        }
+        let pos_len = bin.len();
+
+        let mut accessors = vec![format!(
+            r#"{{"bufferView":0,"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            self.positions.len(), min.x, min.y, min.z, max.x, max.y, max.z
+        )];
+        let mut materials = Vec::new();
+        let mut primitives = Vec::new();
+        for (m, tris) in self.groups.iter().enumerate().filter(|(_, t)| !t.is_empty()) {
+            accessors.push(format!(
+                r#"{{"bufferView":1,"byteOffset":{},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+                bin.len() - pos_len, tris.len() * 3
+            ));
+            for i in tris.iter().flatten() {
+                bin.extend(i.to_le_bytes());
+            }
+            let c = self.materials[m];
+            materials.push(format!(
+                r#"{{"name":"c{}","pbrMetallicRoughness":{{"baseColorFactor":[{},{},{},{}],"metallicFactor":0,"roughnessFactor":1}}}}"#,
+                m, c[0], c[1], c[2], c[3]
+            ));
+            primitives.push(format!(
+                r#"{{"attributes":{{"POSITION":0}},"indices":{},"material":{}}}"#,
+                accessors.len() - 1, materials.len() - 1
+            ));
+        }
+
+        let mut json = format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"fractal-valley"}},"scene":0,"scenes":[{{"nodes":[0]}}],"#,
+                r#""nodes":[{{"mesh":0,"name":"valley"}}],"meshes":[{{"primitives":[{}]}}],"materials":[{}],"accessors":[{}],"#,
+                r#""bufferViews":[{{"buffer":0,"byteLength":{},"target":34962}},{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34963}}],"#,
+                r#""buffers":[{{"byteLength":{}}}]}}"#
+            ),
+            primitives.join(","), materials.join(","), accessors.join(","),
+            pos_len, pos_len, bin.len() - pos_len, bin.len()
+        ).into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let total = 12 + 8 + json.len() + 8 + bin.len();
+        let mut out = Vec::with_capacity(total);
+        out.extend(0x46546C67u32.to_le_bytes());
+        out.extend(2u32.to_le_bytes());
+        out.extend((total as u32).to_le_bytes());
+        out.extend((json.len() as u32).to_le_bytes());
+        out.extend(0x4E4F534Au32.to_le_bytes());
+        out.extend(json);
+        out.extend((bin.len() as u32).to_le_bytes());
+        out.extend(0x004E4942u32.to_le_bytes());
+        out.extend(bin);
+        out
+    }
+}
@@ -0,0 +1,554 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    deep::BigComplex,
+    shared::{INITIAL_BOUNDS, JULIA_BOUNDS, MAX_ITER, MAX_ITER_LIMIT, MIN_ITER}
+};
+
+// large bailout keeps the log-log smoothing free of visible seams
+pub const SMOOTH_BAILOUT: f64 = 256. * 256.;
+// interior measures are negative values down to -INTERIOR_SPAN, one gradient cycle for the palette
+pub const INTERIOR_SPAN: f32 = 8.;
+// orbits closer than this to the cycle start count as having returned
+const PERIOD_EPS: f64 = 1e-9;
+// cross trap, arm distance that maps to the end of the span
+const TRAP_SIZE: f64 = 0.25;
+// smallest smooth value, 0 is the set so an escaped point must never reach it
+const MIN_SMOOTH: f64 = 1e-3;
+
+// ---
+
+// normalized to the bailout of 2, so it stays close to the integer count
+pub fn smooth_iter(n: usize, r2: f64, degree: f64, max_iter: usize) -> f32 {
+    let log_z = 0.5 * r2.ln();
+    let nu = n as f64 - (log_z / 2f64.ln()).ln() / degree.ln();
+    nu.clamp(MIN_SMOOTH, (max_iter - 1) as f64) as f32
+}
+
+// value of an orbit stopped after n steps at z, first is the step it passed 2, None when it never escaped
+pub fn escape_value(n: usize, first: Option<usize>, z: (f64, f64), degree: f64, max_iter: usize, smooth: bool) -> Option<f32> {
+    // a point outside from the start still escaped, 0 is the flat interior
+    if !smooth {
+        return first.filter(|f| *f < max_iter).map(|f| f.max(1) as f32);
+    }
+    (n < max_iter).then(|| smooth_iter(n, z.0 * z.0 + z.1 * z.1, degree, max_iter))
+}
+
+// 0.5 |z| ln|z| / |dz|, inf and nan from a derivative that ran away count as no distance
+pub fn distance_estimate(z: (f64, f64), dz: (f64, f64)) -> f64 {
+    let r = (z.0 * z.0 + z.1 * z.1).sqrt();
+    let d = 0.5 * r * r.ln() / (dz.0 * dz.0 + dz.1 * dz.1).sqrt();
+    if d.is_finite() {d} else {0.}
+}
+
+// ---
+
+fn mul(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+fn mul_big(a: &BigComplex, b: &BigComplex) -> BigComplex {
+    (
+        a.0.mul(&b.0).sub(&a.1.mul(&b.1)),
+        a.0.mul(&b.1).add(&a.1.mul(&b.0))
+    )
+}
+
+// |a + d| - |a| without losing d to cancellation when a is large, the abs folding formulas perturb with it
+fn diff_abs(a: f64, d: f64) -> f64 {
+    match (a >= 0., a + d >= 0.) {
+        (true, true) => d,
+        (true, false) => -d - 2. * a,
+        (false, true) => d + 2. * a,
+        (false, false) => -d
+    }
+}
+
+// ---
+
+pub trait FractalFormula: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn bounds(&self) -> ((f64, f64), (f64, f64));
+    fn step(&self, z: (f64, f64), c: (f64, f64)) -> (f64, f64);
+
+    // exponent of z, used for the smooth iteration count
+    fn degree(&self) -> f64 {
+        2.
+    }
+
+    // shortcut for points known to be inside the set, names the component they are in
+    fn interior(&self, _x: f64, _y: f64) -> Option<&'static str> {
+        None
+    }
+
+    fn is_interior(&self, x: f64, y: f64) -> bool {
+        self.interior(x, y).is_some()
+    }
+
+    // None when the point doesn't escape within max_iter
+    fn escape(&self, z0: (f64, f64), c: (f64, f64), max_iter: usize, smooth: bool) -> Option<f32> {
+        let bailout = if smooth {SMOOTH_BAILOUT} else {4.0};
+        let mut z = z0;
+        let mut n = 0;
+        while (z.0 * z.0 + z.1 * z.1 < bailout) && (n < max_iter) {
+            z = self.step(z, c);
+            n += 1;
+        }
+        if n == max_iter {
+            return None;
+        }
+        if !smooth {
+            return Some(n.max(1) as f32);
+        }
+        Some(smooth_iter(n, z.0 * z.0 + z.1 * z.1, self.degree(), max_iter))
+    }
+
+    // derivative of the step by z applied to dz, a finite difference along dz unless the formula knows better
+    fn step_dz(&self, z: (f64, f64), dz: (f64, f64)) -> (f64, f64) {
+        let len = (dz.0 * dz.0 + dz.1 * dz.1).sqrt();
+        if len == 0. {
+            return (0., 0.);
+        }
+        let h = 1e-7 * (1. + (z.0 * z.0 + z.1 * z.1).sqrt());
+        let a = self.step((z.0 + h * dz.0 / len, z.1 + h * dz.1 / len), (0., 0.));
+        let b = self.step(z, (0., 0.));
+        ((a.0 - b.0) / h * len, (a.1 - b.1) / h * len)
+    }
+
+    // escape value and the exterior distance estimate in one pass, the orbit runs on to the large bailout
+    // for the derivative and the step it passed 2 gives the plain count, the distance is 0 when it doesn't escape
+    fn escape_distance(&self, z0: (f64, f64), c: (f64, f64), max_iter: usize, smooth: bool, julia: bool) -> (Option<f32>, f64) {
+        let mut z = z0;
+        let mut dz = (1., 0.);
+        let mut n = 0;
+        let mut first = None;
+        loop {
+            let r2 = z.0 * z.0 + z.1 * z.1;
+            if first.is_none() && r2 >= 4. {
+                first = Some(n);
+            }
+            if r2 >= SMOOTH_BAILOUT || n >= max_iter {
+                break;
+            }
+            dz = self.step_dz(z, dz);
+            if !julia {
+                dz.0 += 1.;
+            }
+            z = self.step(z, c);
+            n += 1;
+        }
+        let distance = if first.is_some() {distance_estimate(z, dz)} else {0.};
+        (escape_value(n, first, z, self.degree(), max_iter, smooth), distance)
+    }
+
+    fn calc_color(&self, x: f64, y: f64, max_iter: usize, smooth: bool) -> Option<f32> {
+        if self.is_interior(x, y) {
+            return None;
+        }
+        self.escape((x, y), (x, y), max_iter, smooth)
+    }
+
+    fn calc_julia(&self, x: f64, y: f64, c: (f64, f64), max_iter: usize, smooth: bool) -> Option<f32> {
+        self.escape((x, y), c, max_iter, smooth)
+    }
+
+    fn calc_color_distance(&self, x: f64, y: f64, max_iter: usize, smooth: bool) -> (Option<f32>, f64) {
+        if self.is_interior(x, y) {
+            return (None, 0.);
+        }
+        self.escape_distance((x, y), (x, y), max_iter, smooth, false)
+    }
+
+    // high precision step for the deep zoom reference orbit, None if the formula can't zoom deep
+    fn step_deep(&self, _z: &BigComplex, _c: &BigComplex) -> Option<BigComplex> {
+        None
+    }
+
+    // next delta of z against the reference orbit value z
+    fn perturb(&self, z: (f64, f64), dz: (f64, f64), dc: (f64, f64)) -> (f64, f64) {
+        let a = self.step((z.0 + dz.0, z.1 + dz.1), (0., 0.));
+        let b = self.step(z, (0., 0.));
+        (a.0 - b.0 + dc.0, a.1 - b.1 + dc.1)
+    }
+}
+
+// ---
+
+pub struct Mandelbrot;
+impl FractalFormula for Mandelbrot {
+    fn name(&self) -> &'static str {
+        "Mandelbrot"
+    }
+
+    fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        INITIAL_BOUNDS
+    }
+
+    fn step(&self, z: (f64, f64), c: (f64, f64)) -> (f64, f64) {
+        (z.0 * z.0 - z.1 * z.1 + c.0, 2. * z.0 * z.1 + c.1)
+    }
+
+    fn step_dz(&self, z: (f64, f64), dz: (f64, f64)) -> (f64, f64) {
+        mul((2. * z.0, 2. * z.1), dz)
+    }
+
+    fn step_deep(&self, z: &BigComplex, c: &BigComplex) -> Option<BigComplex> {
+        let (re, im) = mul_big(z, z);
+        Some((re.add(&c.0), im.add(&c.1)))
+    }
+
+    // 2 z dz + dz^2 + dc
+    fn perturb(&self, z: (f64, f64), dz: (f64, f64), dc: (f64, f64)) -> (f64, f64) {
+        let d = mul((2. * z.0 + dz.0, 2. * z.1 + dz.1), dz);
+        (d.0 + dc.0, d.1 + dc.1)
+    }
+
+    fn interior(&self, x: f64, y: f64) -> Option<&'static str> {
+        let y2 = y * y;
+        // cardioid check
+        let q = x * x - 0.5 * x + 0.0625 + y2;
+        if y2 >= 4.0 * q * (q + x - 0.25) {
+            return Some("main cardioid");
+        }
+        // bulb check
+        ((x + 1.0) * (x + 1.0) + y2 < 0.0625).then_some("period-2 bulb")
+    }
+}
+
+// ---
+
+pub struct BurningShip;
+impl FractalFormula for BurningShip {
+    fn name(&self) -> &'static str {
+        "Burning Ship"
+    }
+
+    fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        ((-2.2, 1.2), (-2.0, 1.4))
+    }
+
+    fn step(&self, z: (f64, f64), c: (f64, f64)) -> (f64, f64) {
+        (z.0 * z.0 - z.1 * z.1 + c.0, 2. * (z.0 * z.1).abs() + c.1)
+    }
+
+    fn step_deep(&self, z: &BigComplex, c: &BigComplex) -> Option<BigComplex> {
+        let (re, im) = mul_big(z, z);
+        Some((re.add(&c.0), im.abs().add(&c.1)))
+    }
+
+    // re: (2x + dx) dx - (2y + dy) dy, im: 2 (|xy + d(xy)| - |xy|)
+    fn perturb(&self, z: (f64, f64), dz: (f64, f64), dc: (f64, f64)) -> (f64, f64) {
+        let re = (2. * z.0 + dz.0) * dz.0 - (2. * z.1 + dz.1) * dz.1;
+        let dxy = z.0 * dz.1 + z.1 * dz.0 + dz.0 * dz.1;
+        (re + dc.0, 2. * diff_abs(z.0 * z.1, dxy) + dc.1)
+    }
+}
+
+// ---
+
+pub struct Tricorn;
+impl FractalFormula for Tricorn {
+    fn name(&self) -> &'static str {
+        "Tricorn"
+    }
+
+    fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        ((-2.2, 1.6), (-1.9, 1.9))
+    }
+
+    fn step(&self, z: (f64, f64), c: (f64, f64)) -> (f64, f64) {
+        (z.0 * z.0 - z.1 * z.1 + c.0, -2. * z.0 * z.1 + c.1)
+    }
+
+    // conj(2 z dz)
+    fn step_dz(&self, z: (f64, f64), dz: (f64, f64)) -> (f64, f64) {
+        let d = mul((2. * z.0, 2. * z.1), dz);
+        (d.0, -d.1)
+    }
+
+    fn step_deep(&self, z: &BigComplex, c: &BigComplex) -> Option<BigComplex> {
+        let (re, im) = mul_big(z, z);
+        Some((re.add(&c.0), c.1.sub(&im)))
+    }
+
+    // conj(2 z dz + dz^2) + dc
+    fn perturb(&self, z: (f64, f64), dz: (f64, f64), dc: (f64, f64)) -> (f64, f64) {
+        let d = mul((2. * z.0 + dz.0, 2. * z.1 + dz.1), dz);
+        (d.0 + dc.0, -d.1 + dc.1)
+    }
+}
+
+// ---
+
+pub struct Multibrot(pub u32);
+impl FractalFormula for Multibrot {
+    fn name(&self) -> &'static str {
+        "Multibrot"
+    }
+
+    fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        ((-1.6, 1.6), (-1.6, 1.6))
+    }
+
+    fn degree(&self) -> f64 {
+        self.0 as f64
+    }
+
+    fn step(&self, z: (f64, f64), c: (f64, f64)) -> (f64, f64) {
+        let mut p = z;
+        for _ in 1 .. self.0 {
+            p = (p.0 * z.0 - p.1 * z.1, p.0 * z.1 + p.1 * z.0);
+        }
+        (p.0 + c.0, p.1 + c.1)
+    }
+
+    // n z^(n - 1) dz
+    fn step_dz(&self, z: (f64, f64), dz: (f64, f64)) -> (f64, f64) {
+        let mut p = (self.0 as f64, 0.);
+        for _ in 1 .. self.0 {
+            p = mul(p, z);
+        }
+        mul(p, dz)
+    }
+
+    fn step_deep(&self, z: &BigComplex, c: &BigComplex) -> Option<BigComplex> {
+        let mut p = z.clone();
+        for _ in 1 .. self.0 {
+            p = mul_big(&p, z);
+        }
+        Some((p.0.add(&c.0), p.1.add(&c.1)))
+    }
+
+    // (z + dz)^n - z^n = dz * sum((z + dz)^k * z^(n - 1 - k)) + dc
+    fn perturb(&self, z: (f64, f64), dz: (f64, f64), dc: (f64, f64)) -> (f64, f64) {
+        let zd = (z.0 + dz.0, z.1 + dz.1);
+        let mut acc = (1., 0.);
+        let mut pz = (1., 0.);
+        for _ in 1 .. self.0 {
+            pz = mul(pz, z);
+            acc = mul(acc, zd);
+            acc = (acc.0 + pz.0, acc.1 + pz.1);
+        }
+        let d = mul(dz, acc);
+        (d.0 + dc.0, d.1 + dc.1)
+    }
+}
+
+// ---
+
+pub struct Celtic;
+impl FractalFormula for Celtic {
+    fn name(&self) -> &'static str {
+        "Celtic"
+    }
+
+    fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        ((-2.2, 1.4), (-1.8, 1.8))
+    }
+
+    fn step(&self, z: (f64, f64), c: (f64, f64)) -> (f64, f64) {
+        ((z.0 * z.0 - z.1 * z.1).abs() + c.0, 2. * z.0 * z.1 + c.1)
+    }
+
+    fn step_deep(&self, z: &BigComplex, c: &BigComplex) -> Option<BigComplex> {
+        let (re, im) = mul_big(z, z);
+        Some((re.abs().add(&c.0), im.add(&c.1)))
+    }
+
+    // re: |x^2 - y^2 + d(x^2 - y^2)| - |x^2 - y^2|, im: 2 d(xy)
+    fn perturb(&self, z: (f64, f64), dz: (f64, f64), dc: (f64, f64)) -> (f64, f64) {
+        let d2 = (2. * z.0 + dz.0) * dz.0 - (2. * z.1 + dz.1) * dz.1;
+        let dxy = z.0 * dz.1 + z.1 * dz.0 + dz.0 * dz.1;
+        (diff_abs(z.0 * z.0 - z.1 * z.1, d2) + dc.0, 2. * dxy + dc.1)
+    }
+}
+
+// ---
+
+pub static FORMULAS: [&dyn FractalFormula; 5] = [
+    &Mandelbrot,
+    &BurningShip,
+    &Tricorn,
+    &Multibrot(3),
+    &Celtic,
+];
+
+// what the points that never escape are coloured and raised by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InteriorMode {
+    #[default]
+    Flat,
+    // length of the attracting cycle
+    Period,
+    // |z| after the last iteration
+    Magnitude,
+    // iteration where |z| came closest to 0
+    AtomDomain,
+    // closest approach to the axes
+    OrbitTrap
+}
+
+impl InteriorMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Flat => Self::Period,
+            Self::Period => Self::Magnitude,
+            Self::Magnitude => Self::AtomDomain,
+            Self::AtomDomain => Self::OrbitTrap,
+            Self::OrbitTrap => Self::Flat
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Flat => "flat",
+            Self::Period => "period",
+            Self::Magnitude => "final |z|",
+            Self::AtomDomain => "atom domain",
+            Self::OrbitTrap => "orbit trap"
+        }
+    }
+
+    // the measure behind a negative value, as the inspector shows it
+    pub fn describe(self, value: f32) -> String {
+        let t = -value;
+        match self {
+            Self::Flat => String::new(),
+            Self::Period => format!("period {}", t),
+            Self::Magnitude => format!("final |z| {:.4}", t / INTERIOR_SPAN * 2.),
+            Self::AtomDomain => format!("atom domain {}", t),
+            Self::OrbitTrap => format!("orbit trap {:.4}", t / INTERIOR_SPAN * TRAP_SIZE as f32)
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Formula {
+    pub index: usize,
+    pub julia: Option<(f64, f64)>,
+    // fractional escape count instead of the integer one
+    pub smooth: bool,
+    pub max_iter: usize,
+    // scale max_iter with the zoom depth
+    pub auto_iter: bool,
+    // sessions and bookmarks from before interior modes are flat
+    #[serde(default)]
+    pub inside: InteriorMode
+}
+
+impl Default for Formula {
+    fn default() -> Self {
+        Self {
+            index: 0,
+            julia: None,
+            smooth: false,
+            max_iter: MAX_ITER,
+            auto_iter: false,
+            inside: InteriorMode::Flat
+        }
+    }
+}
+
+impl Formula {
+    pub fn get(&self) -> &'static dyn FractalFormula {
+        FORMULAS[self.index % FORMULAS.len()]
+    }
+
+    pub fn next(&self) -> Self {
+        Self {
+            index: (self.index + 1) % FORMULAS.len(),
+            julia: None,
+            ..*self
+        }
+    }
+
+    pub fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        if self.julia.is_some() {JULIA_BOUNDS} else {self.get().bounds()}
+    }
+
+    // None when the point doesn't escape within max_iter
+    pub fn escape(&self, x: f64, y: f64) -> Option<f32> {
+        match self.julia {
+            Some(c) => self.get().calc_julia(x, y, c, self.max_iter, self.smooth),
+            None => self.get().calc_color(x, y, self.max_iter, self.smooth)
+        }
+    }
+
+    // negative measure of a point that never escapes, 0 when the interior is flat or the measure undefined
+    pub fn inside_value(&self, x: f64, y: f64) -> f32 {
+        let f = self.get();
+        let (z0, c) = match self.julia {
+            Some(c) => ((x, y), c),
+            None => ((x, y), (x, y))
+        };
+        let t = match self.inside {
+            InteriorMode::Flat => return 0.,
+            InteriorMode::Period => {
+                // brent's cycle detection, the hare runs ahead and the tortoise jumps to it at powers of two
+                let (mut tortoise, mut hare) = (z0, f.step(z0, c));
+                let (mut power, mut lambda) = (1, 1);
+                let mut n = 0;
+                while (hare.0 - tortoise.0).abs() + (hare.1 - tortoise.1).abs() > PERIOD_EPS {
+                    // a diverging orbit has no period, nan would end the loop with a made up one
+                    let r2 = hare.0 * hare.0 + hare.1 * hare.1;
+                    if n >= self.max_iter || r2 > 4. || r2.is_nan() {
+                        return 0.;
+                    }
+                    if power == lambda {
+                        tortoise = hare;
+                        power *= 2;
+                        lambda = 0;
+                    }
+                    hare = f.step(hare, c);
+                    lambda += 1;
+                    n += 1;
+                }
+                lambda as f32
+            },
+            InteriorMode::Magnitude => {
+                let mut z = z0;
+                for _ in 0 .. self.max_iter {
+                    z = f.step(z, c);
+                }
+                ((z.0 * z.0 + z.1 * z.1).sqrt() * 0.5) as f32 * INTERIOR_SPAN
+            },
+            InteriorMode::AtomDomain | InteriorMode::OrbitTrap => {
+                let mut z = z0;
+                let (mut closest, mut atom, mut trap) = (f64::MAX, 1, f64::MAX);
+                for n in 1 ..= self.max_iter {
+                    z = f.step(z, c);
+                    let r2 = z.0 * z.0 + z.1 * z.1;
+                    if r2 < closest {
+                        closest = r2;
+                        atom = n;
+                    }
+                    trap = trap.min(z.0.abs().min(z.1.abs()));
+                }
+                if self.inside == InteriorMode::AtomDomain {
+                    atom as f32
+                } else {
+                    (trap / TRAP_SIZE).min(1.) as f32 * INTERIOR_SPAN
+                }
+            }
+        };
+        // a measure of 0 would read as flat
+        -t.max(1e-3)
+    }
+
+    // calc_color and the distance in complex units from the set, 0 inside
+    pub fn calc_color_distance(&self, x: f64, y: f64) -> (f32, f64) {
+        let (value, distance) = match self.julia {
+            Some(c) => self.get().escape_distance((x, y), c, self.max_iter, self.smooth, true),
+            None => self.get().calc_color_distance(x, y, self.max_iter, self.smooth)
+        };
+        (value.unwrap_or_else(|| self.inside_value(x, y)), distance)
+    }
+
+    pub fn auto_max_iter(&self, width: f64) -> usize {
+        let initial = self.bounds();
+        let zoom = (initial.0.1 - initial.0.0) / width;
+        let iter = MAX_ITER as f64 * (1. + zoom.log10().max(0.)).powf(1.5);
+        (iter as usize).clamp(MIN_ITER, MAX_ITER_LIMIT)
+    }
+}
@@ -1,8 +1,15 @@
-use bevy::prelude::*;
+use std::sync::Arc;
+
+use bevy::{input::keyboard::KeyboardInput, prelude::*};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    player::PlayerCell, 
-    shared::{TilesCenter, INITIAL_BOUNDS, TILES_COUNT, VALLEY_SIZE, MAX_ITER}
+    deep::{prec_for_width, BigComplex, BigFixed, Orbit, ReferenceOrbit, DEEP_WIDTH},
+    formula::Formula,
+    heights::HeightMap,
+    history::RecordView,
+    player::{PlayerCell, WorldShift},
+    shared::{TilesCenter, TilesCount, INITIAL_BOUNDS, MAX_ITER_LIMIT, MIN_ITER, TILES_COUNT, VALLEY_SIZE}
 };
 
 pub struct FractalPlugin;
@@ -10,133 +17,447 @@ impl Plugin for FractalPlugin {
     fn build(&self, app: &mut App) {
         app
         .init_resource::<FractallCollors>()
-        .insert_resource(FractallBounds{
-            x: INITIAL_BOUNDS.0,
-            y: INITIAL_BOUNDS.1
-        })
-        .add_systems(Update,do_fractal.run_if(resource_changed::<PlayerCell>))
+        .init_resource::<TilesCount>()
+        .init_resource::<Formula>()
+        .init_resource::<JuliaParent>()
+        .init_resource::<ReferenceOrbit>()
+        .init_resource::<DistanceShading>()
+        .insert_resource(FractallBounds::new(INITIAL_BOUNDS))
+        .add_systems(Update, (switch_formula, leave_julia, change_iter, change_shading).run_if(on_event::<KeyboardInput>).before(rebase_origin))
+        .add_systems(Update, (rebase_origin, auto_iter, update_orbit).chain().run_if(resource_changed::<FractallBounds>.or(resource_changed::<Formula>)))
+        .add_systems(Update,do_fractal.after(update_orbit).run_if(
+            resource_changed::<PlayerCell>
+            .or(resource_changed::<FractallBounds>)
+            .or(resource_changed::<Formula>)
+            .or(resource_changed::<TilesCount>)
+            .or(resource_changed::<HeightMap>)
+            .or(resource_changed::<DistanceShading>)
+        ))
         ;
     }
 }
 
 // ---
-#[derive(Resource, Debug)]
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
 pub struct FractallBounds{
     pub x: (f64, f64),
     pub y: (f64, f64),
+    // x and y are offsets from this point, it follows the view centre on deep zooms
+    pub origin: BigComplex
+}
+
+impl FractallBounds {
+    pub fn new(bounds: ((f64, f64), (f64, f64))) -> Self {
+        Self {
+            x: bounds.0,
+            y: bounds.1,
+            origin: BigComplex::default()
+        }
+    }
+
+    pub fn origin_f64(&self) -> (f64, f64) {
+        (self.origin.0.to_f64(), self.origin.1.to_f64())
+    }
+
+    // point of the valley cell, relative to the origin
+    pub fn cell_point(&self, cell: (i32, i32)) -> (f64, f64) {
+        (
+            self.x.0 + cell.0 as f64 * (self.x.1 - self.x.0) / VALLEY_SIZE as f64,
+            self.y.0 + cell.1 as f64 * (self.y.1 - self.y.0) / VALLEY_SIZE as f64
+        )
+    }
+
+    // scales the bounds by f, the point p relative to the origin stays put
+    pub fn zoom_at(&mut self, p: (f64, f64), f: f64) {
+        self.x = (p.0 - (p.0 - self.x.0) * f, p.0 + (self.x.1 - p.0) * f);
+        self.y = (p.1 - (p.1 - self.y.0) * f, p.1 + (self.y.1 - p.1) * f);
+    }
+
+    // moved along by whole valley cells
+    pub fn shifted(&self, d: IVec2) -> Self {
+        let shift = (
+            d.x as f64 * (self.x.1 - self.x.0) / VALLEY_SIZE as f64,
+            d.y as f64 * (self.y.1 - self.y.0) / VALLEY_SIZE as f64
+        );
+        Self {
+            x: (self.x.0 + shift.0, self.x.1 + shift.0),
+            y: (self.y.0 + shift.1, self.y.1 + shift.1),
+            origin: self.origin.clone()
+        }
+    }
+
+    // the origin moves on deep zooms, so compare absolute positions
+    pub fn same_view(&self, o: &Self) -> bool {
+        let w = self.x.1 - self.x.0;
+        let eps = w * 1e-6;
+        let dx = self.origin.0.sub(&o.origin.0).to_f64() + self.x.0 - o.x.0;
+        let dy = self.origin.1.sub(&o.origin.1).to_f64() + self.y.0 - o.y.0;
+        (w - (o.x.1 - o.x.0)).abs() <= eps && dx.abs() <= eps && dy.abs() <= eps
+    }
 }
 
+// everything needed to colour a point, cheap to clone
+#[derive(Clone)]
+pub struct FractalView {
+    pub formula: Formula,
+    pub origin: (f64, f64),
+    pub orbit: Option<Arc<Orbit>>
+}
+
+impl FractalView {
+    pub fn new(formula: &Formula, bounds: &FractallBounds, orbit: &ReferenceOrbit) -> Self {
+        Self {
+            formula: *formula,
+            origin: bounds.origin_f64(),
+            orbit: orbit.0.clone()
+        }
+    }
+
+    // x and y are relative to the bounds origin, None when the point doesn't escape within max_iter
+    pub fn escape(&self, x: f64, y: f64) -> Option<f32> {
+        let c = (self.origin.0 + x, self.origin.1 + y);
+        match &self.orbit {
+            Some(_) if self.formula.julia.is_none() && self.formula.get().is_interior(c.0, c.1) => None,
+            Some(orbit) => orbit.calc_color(&self.formula, (x - orbit.center.0, y - orbit.center.1)),
+            None => self.formula.escape(c.0, c.1)
+        }
+    }
+
+    pub fn calc_color(&self, x: f64, y: f64) -> f32 {
+        // the interior measures change slowly, f64 is close enough even on deep zooms
+        self.escape(x, y).unwrap_or_else(|| self.formula.inside_value(self.origin.0 + x, self.origin.1 + y))
+    }
+
+    // calc_color and the distance in complex units, 0 inside the set, from one pass over the orbit
+    pub fn calc_color_distance(&self, x: f64, y: f64) -> (f32, f64) {
+        let c = (self.origin.0 + x, self.origin.1 + y);
+        let (value, distance) = match &self.orbit {
+            Some(orbit) => {
+                if self.formula.julia.is_none() && self.formula.get().is_interior(c.0, c.1) {
+                    (None, 0.)
+                } else {
+                    orbit.calc_color_distance(&self.formula, (x - orbit.center.0, y - orbit.center.1))
+                }
+            },
+            None => return self.formula.calc_color_distance(c.0, c.1)
+        };
+        (value.unwrap_or_else(|| self.formula.inside_value(c.0, c.1)), distance)
+    }
+}
+
+// exterior distance estimate colouring, a glow along the boundary and outlines of the filaments on the map
+#[derive(Resource, Debug, Default)]
+pub struct DistanceShading {
+    pub enabled: bool
+}
+
+impl DistanceShading {
+    // the cells and the map only pay for the derivative when something uses it
+    pub fn needed(&self, heights: &HeightMap) -> bool {
+        self.enabled || heights.mode.needs_distance()
+    }
+}
+
+// bounds of the parent view to return to from the Julia set
+#[derive(Resource, Debug, Default)]
+pub struct JuliaParent(pub Option<FractallBounds>);
+
+// ring buffer, the value of cell (x, z) lives at [(x + offset.0) % size][(z + offset.1) % size]
 #[derive(Resource, Debug)]
-pub struct FractallCollors(pub [[usize; TILES_COUNT]; TILES_COUNT]);
+pub struct FractallCollors {
+    pub size: usize,
+    // a world shift renames the cells, the offset keeps their values where they are
+    offset: (i32, i32),
+    pub values: Vec<f32>,
+    // distance estimates in cells, laid out like the values, empty unless a height mapping asks for them
+    pub distances: Vec<f32>,
+    // first cell of the window, None until computed
+    pub start: Option<(i32, i32)>,
+    // the whole window was recomputed, not only the newly exposed strips
+    pub rebuilt: bool
+}
 
 impl FromWorld for FractallCollors {
     fn from_world(_world: &mut World) -> Self {
-        Self([[0; TILES_COUNT]; TILES_COUNT])
+        Self {
+            size: TILES_COUNT,
+            offset: (0, 0),
+            values: vec![0.; TILES_COUNT * TILES_COUNT],
+            distances: Vec::new(),
+            start: None,
+            rebuilt: false
+        }
     }
 }
 
-//  ---
+impl FractallCollors {
+    fn index(&self, cell: (i32, i32)) -> usize {
+        let size = self.size as i32;
+        ((cell.0 + self.offset.0).rem_euclid(size) * size + (cell.1 + self.offset.1).rem_euclid(size)) as usize
+    }
 
-fn calc_center(player_cell: (usize, usize), center_cell: (usize, usize)) -> (usize, usize) {
-    let diff_x = center_cell.0 as i32 - player_cell.0 as i32;
-    let diff_z = center_cell.1 as i32 - player_cell.1 as i32;
+    pub fn get(&self, cell: (i32, i32)) -> f32 {
+        self.values[self.index(cell)]
+    }
 
-    let half = TILES_COUNT as i32  / 2;
+    pub fn distance(&self, cell: (i32, i32)) -> f32 {
+        self.distances.get(self.index(cell)).copied().unwrap_or(0.)
+    }
 
-    if diff_x.abs() > half + 1||  diff_z.abs() > half + 1 {
-        return player_cell;
-    } 
+    fn set(&mut self, cell: (i32, i32), value: f32) {
+        let i = self.index(cell);
+        self.values[i] = value;
+    }
 
-    let new_x = if diff_x.abs() > half {
-        center_cell.0 as i32 +  TILES_COUNT as i32 * -diff_x.signum()
-    } else {
-        center_cell.0 as i32
-    };
+    fn set_distance(&mut self, cell: (i32, i32), distance: f32) {
+        let i = self.index(cell);
+        self.distances[i] = distance;
+    }
 
-    let new_z = if diff_z.abs() > half {
-        center_cell.1 as i32 + TILES_COUNT as i32* -diff_z.signum()
-    } else {
-        center_cell.1 as i32
-    };
-    (new_x as usize, new_z as usize)
+    // cell c becomes c - d and keeps its value
+    fn shift(&mut self, d: IVec2) {
+        let size = self.size as i32;
+        self.offset = ((self.offset.0 + d.x).rem_euclid(size), (self.offset.1 + d.y).rem_euclid(size));
+        self.start = self.start.map(|s| (s.0 - d.x, s.1 - d.y));
+    }
+
+    // cells covered by the window
+    pub fn range(&self) -> Option<((i32, i32), (i32, i32))> {
+        let start = self.start?;
+        let size = self.size as i32;
+        Some(((start.0, start.0 + size), (start.1, start.1 + size)))
+    }
 }
 
-// ---
+//  ---
 
-fn do_fractal(
+#[allow(clippy::too_many_arguments)]
+pub fn do_fractal(
     player_cell: Res<PlayerCell>,
     mut colors: ResMut<FractallCollors>,
     bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    orbit: Res<ReferenceOrbit>,
+    tiles: Res<TilesCount>,
+    heights: Res<HeightMap>,
+    shading: Res<DistanceShading>,
     mut center_cell: ResMut<TilesCenter>,
-    mut not_first: Local<bool>
+    mut er: EventReader<WorldShift>,
+    mut last: Local<Option<FractallBounds>>
 ) {
-    let PlayerCell(px, pz) = *player_cell;
-    let TilesCenter(cx, cz) = *center_cell; 
-
-    let cell = calc_center((px,pz), (cx, cz));
+    // bounds moved along by a world shift still show the same points, only the cells are renamed
+    let shift: IVec2 = er.read().map(|e| e.0).sum();
+    let moved = bounds.is_changed() && shift != IVec2::ZERO
+        && last.as_ref().is_some_and(|l| l.shifted(shift).same_view(&bounds));
+    if bounds.is_changed() {
+        *last = Some(bounds.clone());
+    }
+    if moved {
+        colors.shift(shift);
+    }
+    let cell = (player_cell.0, player_cell.1);
+    let with_distance = shading.needed(&heights);
+    if !with_distance && !colors.distances.is_empty() {
+        colors.distances = Vec::new();
+        colors.rebuilt = false;
+    }
+    // distances only exist for cells computed while they were asked for
+    let missing = with_distance && colors.distances.is_empty();
+    let forced = (bounds.is_changed() && !moved) || formula.is_changed() || tiles.is_changed() || missing;
 
-    if (cell == (cx, cz)) && *not_first {
+    if (cell == (center_cell.0, center_cell.1)) && !forced && colors.start.is_some() {
         return;
     }
-    if !*not_first {
-        *not_first = true;
-    }
     center_cell.0 = cell.0;
     center_cell.1 = cell.1;
-    let half = TILES_COUNT / 2;
+    let size = tiles.0;
+    if colors.size != size || missing {
+        colors.size = size;
+        colors.offset = (0, 0);
+        colors.values = vec![0.; size * size];
+        colors.distances = if with_distance {vec![0.; size * size]} else {Vec::new()};
+    }
 
-// ============================================================================================================================================================================================
+    let size = size as i32;
+    let start = (cell.0 - size / 2, cell.1 - size / 2);
+    let old = if forced {None} else {colors.start};
+    let in_old = |x: i32, z: i32| old.is_some_and(|o| {
+        (o.0 .. o.0 + size).contains(&x) && (o.1 .. o.1 + size).contains(&z)
+    });
 
-    let start = (
-        if half as usize> cell.0 {0} else {cell.0 - half as usize},
-        if half as usize> cell.1 {0} else {cell.1 - half as usize},
-    );
+    let view = FractalView::new(&formula, &bounds, &orbit);
+    let cell_width = (bounds.x.1 - bounds.x.0) / VALLEY_SIZE as f64;
+    let mut computed = 0;
 
-    let step_x = (bounds.x.1 - bounds.x.0) / VALLEY_SIZE as f64;
-    let step_y =  (bounds.y.1 - bounds.y.0) / VALLEY_SIZE as f64;
+    for x in start.0 .. start.0 + size {
+        for z in start.1 .. start.1 + size {
+            if in_old(x, z) {
+                continue;
+            }
+            let p = bounds.cell_point((x, z));
+            if with_distance {
+                let (value, distance) = view.calc_color_distance(p.0, p.1);
+                colors.set((x, z), value);
+                colors.set_distance((x, z), (distance / cell_width) as f32);
+            } else {
+                colors.set((x, z), view.calc_color(p.0, p.1));
+            }
+            computed += 1;
+        }
+    }
+    colors.start = Some(start);
+    colors.rebuilt = computed == size * size;
+}
 
-    let x0 = bounds.x.0 + start.0 as f64 * step_x;
-    let y0 = bounds.y.0 + start.1 as f64 * step_y;
-    
-    let mut x = x0;
-    let mut y = y0;
+// ---
 
-    for i in 0 .. TILES_COUNT {
-        for j in  0 .. TILES_COUNT {
-            colors.0[i][j] = calc_color(x, y);            
-            y += step_y; 
-        }
-        x += step_x;
-        y = y0;
+fn switch_formula(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut formula: ResMut<Formula>,
+    mut bounds: ResMut<FractallBounds>,
+    mut parent: ResMut<JuliaParent>,
+    mut rw: EventWriter<RecordView>
+) {
+    if keys.just_pressed(KeyCode::KeyF) {
+        *formula = formula.next();
+        parent.0 = None;
+        *bounds = FractallBounds::new(formula.bounds());
+        rw.send_default();
+    }
+    if keys.just_pressed(KeyCode::KeyB) {
+        formula.smooth = !formula.smooth;
     }
+    if keys.just_pressed(KeyCode::KeyU) {
+        formula.inside = formula.inside.next();
+        info!("interior: {}", formula.inside.name());
+    }
+}
+
+// ---
 
+fn change_iter(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut formula: ResMut<Formula>,
+) {
+    if keys.just_pressed(KeyCode::KeyI) {
+        formula.auto_iter = !formula.auto_iter;
+    }
+    if formula.auto_iter {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Equal) {
+        formula.max_iter = (formula.max_iter * 2).min(MAX_ITER_LIMIT);
+    }
+    if keys.just_pressed(KeyCode::Minus) {
+        formula.max_iter = (formula.max_iter / 2).max(MIN_ITER);
+    }
 }
 
 // ---
 
-pub fn calc_color(x : f64, y : f64) -> usize {
-    let mut lx = x;
-    let mut ly = y;
-    let mut n = 0;
-    let ly2 = ly * ly;
-    let lx2 = lx * lx;
+fn change_shading(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut shading: ResMut<DistanceShading>
+) {
+    if keys.just_pressed(KeyCode::KeyG) {
+        shading.enabled = !shading.enabled;
+    }
+}
 
-    // cardioid check
-    let q = lx2 - 0.5 * lx  + 0.0625 + ly2;
-    if ly2  >= 4.0 * q * (q + lx - 0.25) {
-         return 0;
+// ---
+
+pub fn rebase_origin(
+    mut bounds: ResMut<FractallBounds>
+) {
+    let width = bounds.x.1 - bounds.x.0;
+    let center = ((bounds.x.0 + bounds.x.1) * 0.5, (bounds.y.0 + bounds.y.1) * 0.5);
+    if width < DEEP_WIDTH {
+        if center.0.abs().max(center.1.abs()) <= width {
+            return;
+        }
+        let prec = prec_for_width(width).max(bounds.origin.0.prec());
+        bounds.origin = (
+            bounds.origin.0.add(&BigFixed::from_f64(center.0, prec)),
+            bounds.origin.1.add(&BigFixed::from_f64(center.1, prec))
+        );
+        bounds.x = (bounds.x.0 - center.0, bounds.x.1 - center.0);
+        bounds.y = (bounds.y.0 - center.1, bounds.y.1 - center.1);
+    } else if width > DEEP_WIDTH * 10. && !(bounds.origin.0.is_zero() && bounds.origin.1.is_zero()) {
+        let origin = bounds.origin_f64();
+        bounds.x = (bounds.x.0 + origin.0, bounds.x.1 + origin.0);
+        bounds.y = (bounds.y.0 + origin.1, bounds.y.1 + origin.1);
+        bounds.origin = BigComplex::default();
     }
-    // bulb check
-    if (lx + 1.0) * (lx + 1.0) + ly2 < 0.0625 {
-         return 0;
+}
+
+// ---
+
+pub fn update_orbit(
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    mut orbit: ResMut<ReferenceOrbit>,
+    mut er: EventReader<WorldShift>,
+    mut last: Local<Option<FractallBounds>>
+) {
+    // after a world shift the old reference is still inside the view and relative to the same origin
+    let shift: IVec2 = er.read().map(|e| e.0).sum();
+    let moved = shift != IVec2::ZERO && !formula.is_changed()
+        && last.as_ref().is_some_and(|l| l.origin == bounds.origin && l.shifted(shift).same_view(&bounds));
+    *last = Some(bounds.clone());
+    if moved {
+        return;
     }
+    let width = bounds.x.1 - bounds.x.0;
+    orbit.0 = if width < DEEP_WIDTH {
+        let center = ((bounds.x.0 + bounds.x.1) * 0.5, (bounds.y.0 + bounds.y.1) * 0.5);
+        Orbit::compute(&formula, &bounds.origin, center, prec_for_width(width)).map(Arc::new)
+    } else {
+        None
+    };
+}
 
-    while  (lx * lx + ly * ly  < 4.0) && (n < MAX_ITER) {
-        let lxt = lx * lx - ly * ly + x;
-        ly = 2. * lx * ly + y;
-        lx = lxt;
-        n += 1;
+// ---
+
+pub fn auto_iter(
+    bounds: Res<FractallBounds>,
+    mut formula: ResMut<Formula>,
+) {
+    if !formula.auto_iter {
+        return;
+    }
+    let max_iter = formula.auto_max_iter(bounds.x.1 - bounds.x.0);
+    if formula.max_iter != max_iter {
+        formula.max_iter = max_iter;
     }
+}
 
-    if n == MAX_ITER {0} else {n}
+// ---
+
+pub fn enter_julia(
+    c: (f64, f64),
+    formula: &mut Formula,
+    bounds: &mut FractallBounds,
+    parent: &mut JuliaParent
+) {
+    if formula.julia.is_none() {
+        parent.0 = Some(bounds.clone());
+    }
+    formula.julia = Some(c);
+    *bounds = FractallBounds::new(formula.bounds());
+}
+
+// ---
+
+fn leave_julia(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut formula: ResMut<Formula>,
+    mut bounds: ResMut<FractallBounds>,
+    mut parent: ResMut<JuliaParent>,
+    mut rw: EventWriter<RecordView>
+) {
+    if keys.just_pressed(KeyCode::KeyJ) && formula.julia.is_some() {
+        formula.julia = None;
+        *bounds = parent.0.take().unwrap_or_else(|| FractallBounds::new(formula.bounds()));
+        rw.send_default();
+    }
 }
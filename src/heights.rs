@@ -0,0 +1,343 @@
+use bevy::{input::keyboard::KeyboardInput, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    formula::INTERIOR_SPAN,
+    fractal::FractallCollors,
+    shared::CELL_HEIGHT
+};
+
+pub struct HeightsPlugin;
+impl Plugin for HeightsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<HeightMap>()
+        .init_resource::<HeightEdit>()
+        .add_systems(Startup, startup)
+        .add_systems(Update, height_keys.run_if(on_event::<KeyboardInput>))
+        .add_systems(Update, rebuild_panel.after(height_keys).run_if(
+            resource_changed::<HeightMap>
+            .or(resource_changed::<HeightEdit>)
+        ))
+        ;
+    }
+}
+
+// ---
+
+// logarithmic mode, height units per e-fold of the iteration value
+const LOG_GAIN: f32 = 8.;
+// distance mode, height units per doubling of the distance in cells
+const DISTANCE_GAIN: f32 = 4.;
+// valley mode, depth in height units and the distance in cells where half of it is reached
+const VALLEY_DEPTH: f32 = 24.;
+const VALLEY_WIDTH: f32 = 16.;
+// custom curve, height units of a point at 1
+const CURVE_SPAN: f32 = 64.;
+// interior measures raise the set by up to this much above its own level
+const INTERIOR_RELIEF: f32 = 8.;
+const CURVE_POINTS: usize = 9;
+const CURVE_BAR_HEIGHT: f32 = 60.;
+const SCALE_RANGE: (f32, f32) = (0.25, 8.);
+// one vertical scale per mapping
+const MODES: usize = HeightMode::Curve as usize + 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HeightMode {
+    #[default]
+    Linear,
+    Log,
+    // upside down, the set is a plateau and its boundary a moat
+    Inverted,
+    Distance,
+    // smooth slopes down to the boundary, levelling off far away
+    Valley,
+    Clamped,
+    Curve
+}
+
+impl HeightMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Linear => Self::Log,
+            Self::Log => Self::Inverted,
+            Self::Inverted => Self::Distance,
+            Self::Distance => Self::Valley,
+            Self::Valley => Self::Clamped,
+            Self::Clamped => Self::Curve,
+            Self::Curve => Self::Linear
+        }
+    }
+
+    pub fn prev(self) -> Self {
+        let mut m = self;
+        while m.next() != self {
+            m = m.next();
+        }
+        m
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::Log => "logarithmic",
+            Self::Inverted => "inverted",
+            Self::Distance => "distance estimate",
+            Self::Valley => "valley",
+            Self::Clamped => "clamped",
+            Self::Curve => "custom curve"
+        }
+    }
+
+    pub fn needs_distance(self) -> bool {
+        matches!(self, Self::Distance | Self::Valley)
+    }
+}
+
+// terrace height of an iteration value, shared by the tiles, the lod rings, the export and every landing
+#[derive(Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeightMap {
+    pub mode: HeightMode,
+    // vertical scale of each mode, world height of one unit is CELL_HEIGHT * scale,
+    // linear at 1 lifts every iteration by a cell height
+    pub scales: [f32; MODES],
+    // clamped mode, values above it are cut flat
+    pub cap: f32,
+    // custom curve, 0..1 at even steps of the log iteration fraction, the last point is the set
+    pub curve: Vec<f32>
+}
+
+impl Default for HeightMap {
+    fn default() -> Self {
+        Self {
+            mode: HeightMode::Linear,
+            scales: [1.; MODES],
+            cap: 32.,
+            curve: (0 .. CURVE_POINTS).map(|i| i as f32 / (CURVE_POINTS - 1) as f32).collect()
+        }
+    }
+}
+
+// selected curve point
+#[derive(Resource, Default)]
+pub struct HeightEdit {
+    point: usize
+}
+
+#[derive(Component)]
+pub struct HeightPanel;
+
+#[derive(Component, Clone, Copy)]
+pub enum HeightButton {
+    Mode(i32),
+    Scale(f32),
+    Cap(f32),
+    Point(usize),
+    Raise(f32),
+    Reset
+}
+
+// ---
+
+impl HeightMap {
+    // height in units before the scale, distance is in cells and 0 inside the set, negative values are interior measures
+    pub fn units(&self, value: f32, distance: f32, max_iter: usize) -> f32 {
+        if value < 0. {
+            return self.units(0., 0., max_iter) + INTERIOR_RELIEF * (-value).min(INTERIOR_SPAN) / INTERIOR_SPAN;
+        }
+        match self.mode {
+            HeightMode::Linear => value,
+            HeightMode::Log => LOG_GAIN * value.ln_1p(),
+            HeightMode::Inverted => max_iter as f32 - value,
+            HeightMode::Distance => DISTANCE_GAIN * distance.max(0.).ln_1p() / std::f32::consts::LN_2,
+            HeightMode::Valley => {
+                let d = distance.max(0.);
+                VALLEY_DEPTH * d / (d + VALLEY_WIDTH)
+            },
+            HeightMode::Clamped => value.min(self.cap),
+            HeightMode::Curve => CURVE_SPAN * self.curve_at(if value == 0. {1.} else {value.ln_1p() / (max_iter as f32).ln_1p()})
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scales[self.mode as usize]
+    }
+
+    // world height of the top of a terrace
+    pub fn top(&self, value: f32, distance: f32, max_iter: usize) -> f32 {
+        self.units(value, distance, max_iter) * CELL_HEIGHT * self.scale() + CELL_HEIGHT / 2.
+    }
+
+    pub fn cell_top(&self, colors: &FractallCollors, cell: (i32, i32), max_iter: usize) -> f32 {
+        self.top(colors.get(cell), colors.distance(cell), max_iter)
+    }
+
+    // piecewise linear through the curve points
+    fn curve_at(&self, t: f32) -> f32 {
+        let Some(last) = self.curve.len().checked_sub(1) else {
+            return 0.;
+        };
+        let x = t.clamp(0., 1.) * last as f32;
+        let i = (x.floor() as usize).min(last.saturating_sub(1));
+        let next = self.curve.get(i + 1).copied().unwrap_or(self.curve[i]);
+        self.curve[i] + (next - self.curve[i]) * (x - i as f32)
+    }
+}
+
+// ---
+
+fn startup(
+    mut cmd: Commands
+) {
+    cmd.spawn((
+        HeightPanel,
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(40.),
+            top: Val::Px(10.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0., 0., 0., 0.7)),
+        ZIndex(20),
+        Visibility::Hidden
+    ));
+}
+
+// ---
+
+fn height_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    panel_q: Single<&mut Visibility, With<HeightPanel>>,
+    mut heights: ResMut<HeightMap>
+) {
+    if !keys.just_pressed(KeyCode::KeyH) {
+        return;
+    }
+    if keys.pressed(KeyCode::ShiftLeft) {
+        heights.mode = heights.mode.next();
+        info!("height mapping: {}", heights.mode.name());
+    } else {
+        let mut vis = panel_q.into_inner();
+        *vis = if *vis == Visibility::Visible {Visibility::Hidden} else {Visibility::Visible};
+    }
+}
+
+// ---
+
+fn rebuild_panel(
+    mut cmd: Commands,
+    panel_q: Single<Entity, With<HeightPanel>>,
+    heights: Res<HeightMap>,
+    edit: Res<HeightEdit>
+) {
+    let panel = panel_q.into_inner();
+    cmd.entity(panel).despawn_descendants();
+    cmd.entity(panel).with_children(|parent| {
+        let button = |parent: &mut ChildBuilder, label: &str, button: HeightButton| {
+            parent.spawn((
+                button,
+                Node {
+                    padding: UiRect::axes(Val::Px(6.), Val::Px(2.)),
+                    border: UiRect::all(Val::Px(1.)),
+                    ..default()
+                },
+                BorderColor(Color::WHITE),
+                Text::new(label),
+                TextFont {font_size: 14., ..default()}
+            ))
+            .observe(on_button);
+        };
+        let row = |parent: &mut ChildBuilder, label: String, buttons: &[(&str, HeightButton)]| {
+            parent.spawn(Node {
+                column_gap: Val::Px(6.),
+                align_items: AlignItems::Center,
+                ..default()
+            })
+            .with_children(|row| {
+                row.spawn((
+                    Node {width: Val::Px(150.), ..default()},
+                    Text::new(label),
+                    TextFont {font_size: 14., ..default()}
+                ));
+                for (label, b) in buttons {
+                    button(row, label, *b);
+                }
+            });
+        };
+
+        parent.spawn((
+            Text::new("Heights (H hide, LShift + H next mapping)"),
+            TextFont {font_size: 14., ..default()}
+        ));
+        row(parent, heights.mode.name().to_string(), &[("<", HeightButton::Mode(-1)), (">", HeightButton::Mode(1))]);
+        row(parent, format!("vertical scale {:.2}", heights.scale()), &[("-", HeightButton::Scale(-0.25)), ("+", HeightButton::Scale(0.25))]);
+        if heights.mode == HeightMode::Clamped {
+            row(parent, format!("cap {:.0}", heights.cap), &[("-", HeightButton::Cap(-4.)), ("+", HeightButton::Cap(4.))]);
+        }
+        if heights.mode != HeightMode::Curve {
+            return;
+        }
+
+        // one bar per curve point, click to select, the last one is the set
+        parent.spawn(Node {
+            height: Val::Px(CURVE_BAR_HEIGHT),
+            column_gap: Val::Px(2.),
+            align_items: AlignItems::FlexEnd,
+            ..default()
+        })
+        .with_children(|bars| {
+            for (i, h) in heights.curve.iter().enumerate() {
+                bars.spawn((
+                    HeightButton::Point(i),
+                    Node {
+                        width: Val::Px(16.),
+                        height: Val::Px((h * CURVE_BAR_HEIGHT).max(2.)),
+                        ..default()
+                    },
+                    BackgroundColor(if i == edit.point {Color::WHITE} else {Color::srgb(0.5, 0.5, 0.5)})
+                ))
+                .observe(on_button);
+            }
+        });
+        let point = edit.point.min(heights.curve.len().saturating_sub(1));
+        let label = if point + 1 == heights.curve.len() {"set".to_string()} else {format!("point {}", point + 1)};
+        row(parent, format!("{} {:.2}", label, heights.curve.get(point).copied().unwrap_or_default()), &[
+            ("<", HeightButton::Point(point.saturating_sub(1))), (">", HeightButton::Point((point + 1).min(CURVE_POINTS - 1))),
+            ("-", HeightButton::Raise(-0.05)), ("+", HeightButton::Raise(0.05)),
+            ("reset", HeightButton::Reset)
+        ]);
+    });
+}
+
+// ---
+
+fn on_button(
+    click: Trigger<Pointer<Click>>,
+    button_q: Query<&HeightButton>,
+    mut heights: ResMut<HeightMap>,
+    mut edit: ResMut<HeightEdit>
+) {
+    let Ok(button) = button_q.get(click.entity()) else {
+        return;
+    };
+    match *button {
+        HeightButton::Mode(d) => heights.mode = if d < 0 {heights.mode.prev()} else {heights.mode.next()},
+        HeightButton::Scale(d) => {
+            let i = heights.mode as usize;
+            heights.scales[i] = (heights.scales[i] + d).clamp(SCALE_RANGE.0, SCALE_RANGE.1);
+        },
+        HeightButton::Cap(d) => heights.cap = (heights.cap + d).max(0.),
+        HeightButton::Point(i) => edit.point = i,
+        HeightButton::Raise(d) => {
+            let i = edit.point;
+            if let Some(h) = heights.curve.get_mut(i) {
+                *h = (*h + d).clamp(0., 1.);
+            }
+        },
+        HeightButton::Reset => heights.curve = HeightMap::default().curve
+    }
+}
@@ -0,0 +1,163 @@
+use avian3d::prelude::LinearVelocity;
+use bevy::{input::keyboard::KeyboardInput, prelude::*};
+
+use crate::{
+    formula::Formula,
+    fractal::{rebase_origin, FractallBounds, JuliaParent},
+    player::{Player, PlayerCell},
+    shared::{cell2xz, PLAYER_START_CELL}
+};
+
+pub struct HistoryPlugin;
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<ViewHistory>()
+        .add_event::<RecordView>()
+        .add_systems(Update, history_keys.run_if(on_event::<KeyboardInput>).before(rebase_origin))
+        .add_systems(Update, record.after(rebase_origin))
+        ;
+    }
+}
+
+// ---
+
+const HISTORY_LEN: usize = 64;
+// wheel zooms come a line at a time, only a view that stays put gets recorded
+const SETTLE_SECS: f32 = 0.4;
+
+// sent by the explicit view changes, map zooms and pans, formula and julia switches, bookmark and session jumps,
+// the bounds also move on dives and world shifts but those stay in the same history entry
+#[derive(Event, Debug, Default)]
+pub struct RecordView;
+
+#[derive(Clone)]
+pub struct HistoryEntry {
+    bounds: FractallBounds,
+    formula: usize,
+    julia: Option<(f64, f64)>,
+    cell: (i32, i32)
+}
+
+// pos is the entry on screen, entries after it are the forward list
+#[derive(Resource, Default)]
+pub struct ViewHistory {
+    entries: Vec<HistoryEntry>,
+    pos: usize
+}
+
+// ---
+
+fn same_view(a: &HistoryEntry, b: &HistoryEntry) -> bool {
+    a.formula == b.formula && a.julia == b.julia && a.bounds.same_view(&b.bounds)
+}
+
+// ---
+
+fn record(
+    mut er: EventReader<RecordView>,
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    cell: Res<PlayerCell>,
+    mut history: ResMut<ViewHistory>,
+    time: Res<Time>,
+    mut settle: Local<Option<f32>>
+) {
+    // the start view is the first entry
+    if er.read().count() > 0 || history.entries.is_empty() {
+        *settle = Some(SETTLE_SECS);
+    }
+    let Some(left) = settle.as_mut() else {
+        return;
+    };
+    *left -= time.delta_secs();
+    if *left > 0. {
+        return;
+    }
+    *settle = None;
+
+    let entry = HistoryEntry {
+        bounds: bounds.clone(),
+        formula: formula.index,
+        julia: formula.julia,
+        cell: (cell.0, cell.1)
+    };
+    let pos = history.pos;
+    if history.entries.get(pos).is_some_and(|e| same_view(e, &entry)) {
+        return;
+    }
+    // the view we leave remembers where the player stood
+    if let Some(e) = history.entries.get_mut(pos) {
+        e.cell = entry.cell;
+    }
+    history.entries.truncate(pos + 1);
+    history.entries.push(entry);
+    if history.entries.len() > HISTORY_LEN {
+        history.entries.remove(0);
+    }
+    history.pos = history.entries.len() - 1;
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn history_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<ViewHistory>,
+    mut bounds: ResMut<FractallBounds>,
+    mut formula: ResMut<Formula>,
+    mut julia_parent: ResMut<JuliaParent>,
+    mut cell: ResMut<PlayerCell>,
+    p_q: Single<(&mut Transform, &mut LinearVelocity), With<Player>>,
+    mut ew: EventWriter<RecordView>
+) {
+    if keys.just_pressed(KeyCode::Home) {
+        julia_parent.0 = None;
+        formula.julia = None;
+        *bounds = FractallBounds::new(formula.bounds());
+        place_player(PLAYER_START_CELL, &mut cell, p_q);
+        ew.send_default();
+        return;
+    }
+    if !keys.just_pressed(KeyCode::Backspace) || history.entries.is_empty() {
+        return;
+    }
+    let pos = history.pos;
+    let target = if keys.pressed(KeyCode::ShiftLeft) {
+        pos + 1
+    } else {
+        match pos.checked_sub(1) {
+            Some(p) => p,
+            None => return
+        }
+    };
+    let Some(entry) = history.entries.get(target).cloned() else {
+        return;
+    };
+    history.entries[pos].cell = (cell.0, cell.1);
+    history.pos = target;
+
+    *bounds = entry.bounds;
+    formula.index = entry.formula;
+    formula.julia = entry.julia;
+    if entry.julia.is_none() {
+        julia_parent.0 = None;
+    }
+    place_player(entry.cell, &mut cell, p_q);
+}
+
+// ---
+
+fn place_player(
+    to: (i32, i32),
+    cell: &mut PlayerCell,
+    p_q: Single<(&mut Transform, &mut LinearVelocity), With<Player>>
+) {
+    cell.0 = to.0;
+    cell.1 = to.1;
+    let (mut t, mut lv) = p_q.into_inner();
+    let pos = cell2xz(to);
+    t.translation.x = pos.x;
+    t.translation.z = pos.z;
+    lv.0 = Vec3::ZERO;
+}
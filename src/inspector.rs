@@ -0,0 +1,344 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    input::keyboard::KeyboardInput,
+    picking::PickingBehavior,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    ui::RelativeCursorPosition,
+    window::CursorMoved
+};
+
+use crate::{
+    deep::{prec_for_width, BigComplex, BigFixed, ReferenceOrbit, DEEP_WIDTH},
+    formula::Formula,
+    fractal::{FractalView, FractallBounds, FractallCollors},
+    heights::HeightMap,
+    map::{MapDim, ValleyMap},
+    player::{PlayerCell, WorldShift},
+    shared::{cell2xz, VALLEY_SIZE},
+    GameState
+};
+
+pub struct InspectorPlugin;
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<InspectorSettings>()
+        .init_resource::<OrbitTarget>()
+        .add_observer(attach)
+        .add_systems(Update, inspector_keys.run_if(on_event::<KeyboardInput>))
+        .add_systems(Update, inspect.after(inspector_keys).run_if(in_state(GameState::Map)).run_if(
+            on_event::<CursorMoved>
+            .or(resource_changed::<FractallBounds>)
+            .or(resource_changed::<Formula>)
+            .or(resource_changed::<InspectorSettings>)
+        ))
+        .add_systems(Update, shift_target.run_if(on_event::<WorldShift>))
+        .add_systems(Update, draw_orbit.run_if(in_state(GameState::Game)).run_if(|s: Res<InspectorSettings>| s.orbit))
+        ;
+    }
+}
+
+// ---
+
+// longest orbit drawn, escaping orbits stop much earlier
+const ORBIT_POINTS: usize = 1000;
+const ORBIT_POINTS_3D: usize = 256;
+// world units per complex unit, the same for every cell so neighbours compare
+const ORBIT_SCALE: f32 = 10.;
+// height of z0 above the terrace and the climb of every following step
+const ORBIT_LIFT: f32 = 3.;
+const ORBIT_RISE: f32 = 0.25;
+
+// orbits are drawn over the map and above the valley
+#[derive(Resource, Debug, Default)]
+pub struct InspectorSettings {
+    pub orbit: bool
+}
+
+// cell picked in the valley, the player cell when none
+#[derive(Resource, Debug, Default)]
+pub struct OrbitTarget(pub Option<(i32, i32)>);
+
+#[derive(Component)]
+pub struct Tooltip;
+
+#[derive(Component)]
+pub struct OrbitOverlay;
+
+#[derive(Resource)]
+pub struct OrbitImage(Handle<Image>);
+
+// ---
+
+fn attach(
+    trg: Trigger<OnAdd, ValleyMap>,
+    mut cmd: Commands,
+    map_dim: Res<MapDim>,
+    mut images: ResMut<Assets<Image>>
+) {
+    let image_h = images.add(Image::new_fill(
+        Extent3d {width: map_dim.0, height: map_dim.1, depth_or_array_layers: 1},
+        TextureDimension::D2,
+        &[0, 0, 0, 0],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::all()
+    ));
+    cmd.insert_resource(OrbitImage(image_h.clone()));
+    cmd.entity(trg.entity()).with_children(|parent| {
+        parent.spawn((
+            OrbitOverlay,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                ..default()
+            },
+            ImageNode::new(image_h),
+            ZIndex(5),
+            PickingBehavior::IGNORE
+        ));
+        parent.spawn((
+            Tooltip,
+            Node {
+                position_type: PositionType::Absolute,
+                padding: UiRect::all(Val::Px(4.)),
+                ..default()
+            },
+            Text::new(""),
+            TextFont {font_size: 13., ..default()},
+            BackgroundColor(Color::srgba(0., 0., 0., 0.75)),
+            ZIndex(12),
+            Visibility::Hidden,
+            PickingBehavior::IGNORE
+        ));
+    });
+}
+
+// ---
+
+// z0 .. zn of a point relative to the bounds origin, up to and including the first escaped one, as offsets
+// from the point, the Mandelbrot orbit starts at 0, deep views iterate in fixed point so the offsets keep their digits
+fn escape_orbit(formula: &Formula, bounds: &FractallBounds, rel: (f64, f64), len: usize) -> (Vec<(f64, f64)>, bool) {
+    let f = formula.get();
+    let width = bounds.x.1 - bounds.x.0;
+    let mut offsets = Vec::new();
+    if width >= DEEP_WIDTH {
+        let origin = bounds.origin_f64();
+        let point = (origin.0 + rel.0, origin.1 + rel.1);
+        let (mut z, c) = match formula.julia {
+            Some(jc) => (point, jc),
+            None => ((0., 0.), point)
+        };
+        offsets.push((z.0 - point.0, z.1 - point.1));
+        for _ in 0 .. len.min(formula.max_iter) {
+            z = f.step(z, c);
+            offsets.push((z.0 - point.0, z.1 - point.1));
+            if z.0 * z.0 + z.1 * z.1 > 4. {
+                return (offsets, true);
+            }
+        }
+        return (offsets, false);
+    }
+
+    let prec = prec_for_width(width).max(bounds.origin.0.prec());
+    let big = |v: (f64, f64)| (BigFixed::from_f64(v.0, prec), BigFixed::from_f64(v.1, prec));
+    let point: BigComplex = (bounds.origin.0.add(&BigFixed::from_f64(rel.0, prec)), bounds.origin.1.add(&BigFixed::from_f64(rel.1, prec)));
+    let (mut z, c) = match formula.julia {
+        Some(jc) => (point.clone(), big(jc)),
+        None => (big((0., 0.)), point.clone())
+    };
+    let offset = |z: &BigComplex| (z.0.sub(&point.0).to_f64(), z.1.sub(&point.1).to_f64());
+    offsets.push(offset(&z));
+    for _ in 0 .. len.min(formula.max_iter) {
+        let Some(next) = f.step_deep(&z, &c) else {
+            break;
+        };
+        z = next;
+        offsets.push(offset(&z));
+        let zf = (z.0.to_f64(), z.1.to_f64());
+        if zf.0 * zf.0 + zf.1 * zf.1 > 4. {
+            return (offsets, true);
+        }
+    }
+    (offsets, false)
+}
+
+// ---
+
+fn inspector_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<InspectorSettings>,
+    mut target: ResMut<OrbitTarget>
+) {
+    if keys.just_pressed(KeyCode::KeyO) {
+        settings.orbit = !settings.orbit;
+        target.0 = None;
+    }
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn inspect(
+    settings: Res<InspectorSettings>,
+    map_q: Single<&RelativeCursorPosition, With<ValleyMap>>,
+    tooltip_q: Single<(&mut Node, &mut Text, &mut Visibility), With<Tooltip>>,
+    map_dim: Res<MapDim>,
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    orbit: Res<ReferenceOrbit>,
+    orbit_image: Res<OrbitImage>,
+    mut images: ResMut<Assets<Image>>,
+    // byte offsets of the pixels of the last orbit, only those are cleared
+    mut drawn: Local<Vec<usize>>
+) {
+    let rcp = map_q.into_inner();
+    let (mut node, mut text, mut vis) = tooltip_q.into_inner();
+    if !drawn.is_empty() {
+        if let Some(image) = images.get_mut(&orbit_image.0) {
+            for index in drawn.drain(..) {
+                if let Some(pixel) = image.data.get_mut(index .. index + 4) {
+                    pixel.fill(0);
+                }
+            }
+        }
+        drawn.clear();
+    }
+
+    let Some(v) = rcp.normalized.filter(|_| rcp.mouse_over()) else {
+        *vis = Visibility::Hidden;
+        return;
+    };
+    let size = Vec2::new(map_dim.0 as f32, map_dim.1 as f32);
+    let width = bounds.x.1 - bounds.x.0;
+    let rel = (
+        bounds.x.0 + width * v.x as f64,
+        bounds.y.0 + (bounds.y.1 - bounds.y.0) * v.y as f64
+    );
+
+    // enough digits to tell neighbouring pixels apart
+    let digits = ((-(width / map_dim.0 as f64).log10()).ceil() as i64 + 3).max(6) as usize;
+    let prec = prec_for_width(width).max(bounds.origin.0.prec());
+    let re = bounds.origin.0.add(&BigFixed::from_f64(rel.0, prec));
+    let im = bounds.origin.1.add(&BigFixed::from_f64(rel.1, prec));
+    let c = (re.to_f64(), im.to_f64());
+    let cell = (
+        (VALLEY_SIZE as f32 * v.x).round() as i32,
+        (VALLEY_SIZE as f32 * v.y).round() as i32
+    );
+
+    let escape = match FractalView::new(&formula, &bounds, &orbit).escape(rel.0, rel.1) {
+        Some(n) if formula.smooth => format!("escapes after {:.2} iterations", n),
+        Some(n) => format!("escapes after {} iterations", n),
+        None => {
+            let measure = formula.inside_value(c.0, c.1);
+            let component = formula.julia.is_none().then(|| formula.get().interior(c.0, c.1)).flatten();
+            let parts: Vec<String> = [(measure < 0.).then(|| formula.inside.describe(measure)), component.map(String::from)]
+                .into_iter().flatten().collect();
+            if parts.is_empty() {
+                format!("no escape within {} iterations", formula.max_iter)
+            } else {
+                format!("inside, {}", parts.join(", "))
+            }
+        }
+    };
+    text.0 = format!(
+        "re {}\nim {}\ncell {} / {}\n{}",
+        re.to_decimal(digits), im.to_decimal(digits), cell.0, cell.1, escape
+    );
+    node.left = Val::Px(v.x * size.x + 12.);
+    node.top = Val::Px(v.y * size.y + 12.);
+    *vis = Visibility::Visible;
+
+    if !settings.orbit {
+        return;
+    }
+
+    let to_pixel = |d: (f64, f64)| Vec2::new(
+        ((rel.0 + d.0 - bounds.x.0) / width * size.x as f64) as f32,
+        ((rel.1 + d.1 - bounds.y.0) / (bounds.y.1 - bounds.y.0) * size.y as f64) as f32
+    );
+    let points: Vec<Vec2> = escape_orbit(&formula, &bounds, rel, ORBIT_POINTS).0.into_iter().map(to_pixel).collect();
+    let Some(image) = images.get_mut(&orbit_image.0) else {
+        return;
+    };
+
+    let (w, h) = (map_dim.0 as usize, map_dim.1 as usize);
+    let mut plot = |p: Vec2, color: [u8; 4]| {
+        if p.x >= 0. && p.y >= 0. && p.x < w as f32 && p.y < h as f32 {
+            let index = (p.y as usize * w + p.x as usize) * 4;
+            if let Some(pixel) = image.data.get_mut(index .. index + 4) {
+                pixel.copy_from_slice(&color);
+                drawn.push(index);
+            }
+        }
+    };
+    let inside = |p: Vec2| p.x > -size.x && p.y > -size.y && p.x < 2. * size.x && p.y < 2. * size.y;
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if !inside(a) || !inside(b) {
+            continue;
+        }
+        let steps = a.distance(b).ceil().max(1.) as usize;
+        for i in 0 ..= steps {
+            plot(a.lerp(b, i as f32 / steps as f32), [255, 255, 255, 200]);
+        }
+    }
+    for p in &points {
+        for d in [Vec2::ZERO, Vec2::X, Vec2::Y, Vec2::ONE] {
+            plot(*p + d, [255, 60, 60, 255]);
+        }
+    }
+}
+
+// ---
+
+// the picked cell keeps its complex point when the world moves along
+fn shift_target(
+    mut er: EventReader<WorldShift>,
+    mut target: ResMut<OrbitTarget>
+) {
+    for e in er.read() {
+        target.0 = target.0.map(|c| (c.0 - e.0.x, c.1 - e.0.y));
+    }
+}
+
+// ---
+
+fn draw_orbit(
+    target: Res<OrbitTarget>,
+    player_cell: Res<PlayerCell>,
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    colors: Res<FractallCollors>,
+    heights: Res<HeightMap>,
+    mut gizmos: Gizmos
+) {
+    let cell = target.0.unwrap_or((player_cell.0, player_cell.1));
+    let rel = bounds.cell_point(cell);
+    let in_window = colors.range().is_some_and(|r| (r.0.0 .. r.0.1).contains(&cell.0) && (r.1.0 .. r.1.1).contains(&cell.1));
+    let top = if in_window {heights.cell_top(&colors, cell, formula.max_iter)} else {0.};
+    let base = cell2xz(cell).with_y(top);
+
+    // real part along x like the valley cells, each step one notch higher
+    let (offsets, escaped) = escape_orbit(&formula, &bounds, rel, ORBIT_POINTS_3D);
+    let points: Vec<Vec3> = offsets.into_iter().enumerate()
+    .map(|(k, d)| base + Vec3::new(
+        d.0 as f32 * ORBIT_SCALE,
+        ORBIT_LIFT + k as f32 * ORBIT_RISE,
+        d.1 as f32 * ORBIT_SCALE
+    ))
+    .collect();
+    let hue = |k: usize| Color::hsl(240. * k as f32 / points.len().max(2) as f32, 1., 0.5);
+
+    gizmos.line(base, points[0], Color::WHITE);
+    gizmos.linestrip_gradient(points.iter().enumerate().map(|(k, p)| (*p, hue(k))));
+    for (k, p) in points.iter().enumerate() {
+        gizmos.sphere(Isometry3d::from_translation(*p), 0.15, hue(k));
+    }
+    // the point that got away
+    if let Some(last) = points.last().filter(|_| escaped) {
+        gizmos.sphere(Isometry3d::from_translation(*last), 0.5, Color::srgb(1., 0., 0.));
+    }
+}
@@ -0,0 +1,277 @@
+use bevy::{
+    input::keyboard::KeyboardInput,
+    pbr::{NotShadowCaster, NotShadowReceiver},
+    prelude::*,
+    utils::HashMap
+};
+
+use crate::{
+    deep::ReferenceOrbit,
+    dive::Dive,
+    formula::Formula,
+    fractal::{do_fractal, DistanceShading, FractalView, FractallBounds, FractallCollors},
+    heights::HeightMap,
+    palette::{glow_tile, Palette, PaletteCycle},
+    player::{PlayerCell, WorldShift},
+    shared::{TilesCount, CELL_SIZE, LOD_LEVELS, VALLEY_SIZE},
+    terrace::{cell_block, TerraceBlocks, TerraceMesh, TerraceMorph}
+};
+
+pub struct LodPlugin;
+impl Plugin for LodPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<LodSettings>()
+        .add_systems(Startup, startup)
+        .add_systems(Update, change_levels.run_if(on_event::<KeyboardInput>).before(spawn_rings))
+        .add_systems(Update, spawn_rings.before(update_rings).run_if(resource_changed::<LodSettings>))
+        .add_systems(Update, update_rings.after(do_fractal).run_if(
+            resource_changed::<FractallCollors>
+            .or(resource_changed::<LodSettings>)
+            .or(resource_changed::<Palette>)
+            .or(resource_changed::<PaletteCycle>)
+            .or(resource_changed::<HeightMap>)
+            .or(resource_changed::<DistanceShading>)
+        ))
+        ;
+    }
+}
+
+// ---
+
+// rings of merged meshes around the near field, ring n samples every 2^n cell
+#[derive(Resource, Debug)]
+pub struct LodSettings {
+    pub levels: usize
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {levels: LOD_LEVELS}
+    }
+}
+
+#[derive(Resource)]
+pub struct LodMaterial(Handle<StandardMaterial>);
+
+type CellRange = ((i32, i32), (i32, i32));
+
+#[derive(Component)]
+pub struct LodRing {
+    level: usize,
+    region: Option<CellRange>,
+    inner: Option<CellRange>,
+    // iteration value and distance in cells
+    values: HashMap<(i32, i32), (f32, f32)>
+}
+
+// ---
+
+fn startup(
+    mut cmd: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    cmd.insert_resource(LodMaterial(materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        unlit: true,
+        ..default()
+    })));
+}
+
+// ---
+
+fn change_levels(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<LodSettings>
+) {
+    if keys.just_pressed(KeyCode::BracketRight) {
+        settings.levels = (settings.levels + 1).min(8);
+    }
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        settings.levels = settings.levels.saturating_sub(1);
+    }
+}
+
+// ---
+
+fn spawn_rings(
+    mut cmd: Commands,
+    settings: Res<LodSettings>,
+    material: Res<LodMaterial>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    rings_q: Query<(Entity, &LodRing)>,
+) {
+    let mut count = 0;
+    for (e, ring) in &rings_q {
+        if ring.level > settings.levels {
+            cmd.entity(e).despawn_recursive();
+        } else {
+            count += 1;
+        }
+    }
+    for level in count + 1 ..= settings.levels {
+        cmd.spawn((
+            Mesh3d(meshes.add(TerraceMesh::default().into_mesh())),
+            MeshMaterial3d(material.0.clone()),
+            Transform::IDENTITY,
+            NotShadowCaster,
+            NotShadowReceiver,
+            TerraceBlocks::default(),
+            LodRing {
+                level,
+                region: None,
+                inner: None,
+                values: HashMap::new()
+            },
+            Name::new("LodRing")
+        ));
+    }
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_rings(
+    settings: Res<LodSettings>,
+    colors: Res<FractallCollors>,
+    player_cell: Res<PlayerCell>,
+    tiles: Res<TilesCount>,
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    orbit: Res<ReferenceOrbit>,
+    palette: Res<Palette>,
+    cycle: Res<PaletteCycle>,
+    heights: Res<HeightMap>,
+    shading: Res<DistanceShading>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut rings_q: Query<(Entity, &mut LodRing, &Mesh3d, &mut TerraceBlocks, &mut Transform, Option<&TerraceMorph>)>,
+    mut er: EventReader<WorldShift>,
+    dive: Res<Dive>,
+    mut cmd: Commands
+) {
+    let mut rings: Vec<_> = rings_q.iter_mut().collect();
+    rings.sort_by_key(|(_, r, ..)| r.level);
+
+    // a world shift renames the cells, the rings keep their values and move along
+    let shift: IVec2 = er.read().map(|e| e.0).sum();
+    if shift != IVec2::ZERO {
+        let moved_range = |r: CellRange| ((r.0.0 - shift.x, r.0.1 - shift.x), (r.1.0 - shift.y, r.1.1 - shift.y));
+        for (_, ring, _, _, t, _) in rings.iter_mut() {
+            t.translation -= Vec3::new(shift.x as f32 * CELL_SIZE, 0., shift.y as f32 * CELL_SIZE);
+            ring.values = ring.values.drain().map(|(c, v)| ((c.0 - shift.x, c.1 - shift.y), v)).collect();
+            ring.region = ring.region.map(moved_range);
+            ring.inner = ring.inner.map(moved_range);
+        }
+    }
+
+    let Some(mut inner) = colors.range() else {
+        return;
+    };
+    let forced = colors.is_changed() && colors.rebuilt;
+    // new heights keep the cached values but reshape every ring
+    let reshape = forced || heights.is_changed();
+    // cached values are still good, only the meshes need new colours
+    let recolor = palette.is_changed() || cycle.is_changed() || shading.is_changed();
+    let tile_colors = palette.tile_colors(&cycle);
+    let tint = |v: f32, d: f32| {
+        let color = tile_colors[palette.band(v)];
+        if shading.enabled {glow_tile(color, v, d)} else {color}
+    };
+    let view = FractalView::new(&formula, &bounds, &orbit);
+    let radius = tiles.0 as i32 / 2;
+    let with_distance = shading.needed(&heights);
+    let cell_width = (bounds.x.1 - bounds.x.0) / VALLEY_SIZE as f64;
+
+    for (e, ring, mesh3d, blocks, t, old_morph) in rings.iter_mut().filter(|(_, r, ..)| r.level <= settings.levels) {
+        let block: i32 = 1 << ring.level;
+        let snap = |c: i32| c.div_euclid(block) * block;
+        let region = (
+            (snap(player_cell.0) - radius * block, snap(player_cell.0) + (radius + 1) * block),
+            (snap(player_cell.1) - radius * block, snap(player_cell.1) + (radius + 1) * block)
+        );
+        if forced {
+            ring.values.clear();
+        } else if ring.region == Some(region) && ring.inner == Some(inner) && !reshape {
+            if recolor {
+                if let Some(mesh) = meshes.get_mut(&mesh3d.0) {
+                    blocks.recolor(mesh, tint);
+                }
+            }
+            inner = region;
+            continue;
+        }
+
+        let inside = |r: &CellRange, c: (i32, i32)| {
+            c.0 >= r.0.0 && c.0 + block <= r.0.1 && c.1 >= r.1.0 && c.1 + block <= r.1.1
+        };
+        ring.values.retain(|c, _| inside(&region, *c));
+        let mut mesh = TerraceMesh::default();
+        for x in (region.0.0 .. region.0.1).step_by(block as usize) {
+            for z in (region.1.0 .. region.1.1).step_by(block as usize) {
+                if inside(&inner, (x, z)) {
+                    continue;
+                }
+                let pieces = clip(((x, x + block), (z, z + block)), &inner);
+                let (value, distance) = *ring.values.entry((x, z)).or_insert_with(|| {
+                    let p = bounds.cell_point((x, z));
+                    if with_distance {
+                        let (value, distance) = view.calc_color_distance(p.0, p.1);
+                        (value, (distance / cell_width) as f32)
+                    } else {
+                        (view.calc_color(p.0, p.1), 0.)
+                    }
+                });
+                let color = Color::from(LinearRgba::from_f32_array(tint(value, distance)));
+                let top = heights.top(value, distance, formula.max_iter);
+                for piece in pieces {
+                    let mut b = cell_block((piece.0.0, piece.1.0), block, value, distance, top, color);
+                    b.size = Vec2::new((piece.0.1 - piece.0.0) as f32, (piece.1.1 - piece.1.0) as f32) * CELL_SIZE;
+                    // each level sits a bit lower so finer rings win where they touch
+                    b.top -= ring.level as f32 * 0.01;
+                    b.bottom -= ring.level as f32 * 0.01;
+                    mesh.add_block(&b, false);
+                }
+            }
+        }
+        // a dive starts the new terraces at the old heights
+        let morph = dive.active().then(|| mesh.blocks.morph_from(blocks, *old_morph)).flatten();
+        **blocks = std::mem::take(&mut mesh.blocks);
+        let mut new_mesh = mesh.into_mesh();
+        match morph {
+            Some(mut morph) => {
+                blocks.raise(&mut new_mesh, &mut morph, dive.remaining());
+                cmd.entity(*e).insert(morph);
+            },
+            None if old_morph.is_some() => {
+                cmd.entity(*e).remove::<TerraceMorph>();
+            },
+            None => ()
+        }
+        meshes.insert(&mesh3d.0, new_mesh);
+        if **t != Transform::IDENTITY {
+            **t = Transform::IDENTITY;
+        }
+        ring.region = Some(region);
+        ring.inner = Some(inner);
+        inner = region;
+    }
+}
+
+// parts of a block left outside the finer region, at most a strip on each side
+fn clip(block: CellRange, inner: &CellRange) -> Vec<CellRange> {
+    let ((x0, x1), (z0, z1)) = block;
+    let ((ix0, ix1), (iz0, iz1)) = *inner;
+    if x1 <= ix0 || x0 >= ix1 || z1 <= iz0 || z0 >= iz1 {
+        return vec![block];
+    }
+    let (mx0, mx1) = (x0.max(ix0), x1.min(ix1));
+    [
+        ((x0, ix0), (z0, z1)),
+        ((ix1, x1), (z0, z1)),
+        ((mx0, mx1), (z0, iz0)),
+        ((mx0, mx1), (iz1, z1))
+    ]
+    .into_iter()
+    .filter(|r| r.0.0 < r.0.1 && r.1.0 < r.1.1)
+    .collect()
+}
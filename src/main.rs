@@ -12,12 +12,26 @@ mod shared;
 mod camera;
 mod test;
 mod fractal;
+mod formula;
+mod deep;
 mod valley;
 mod player;
 mod ui;
 mod animator;
 mod map;
 mod target_select;
+mod terrace;
+mod lod;
+mod export;
+mod snapshot;
+mod session;
+mod bookmarks;
+mod history;
+mod minimap;
+mod inspector;
+mod palette;
+mod dive;
+mod heights;
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 pub enum GameState {
@@ -57,7 +71,20 @@ fn main() {
         ui::UIPlugin,
         animator::AnimatorPlugin,
         map::MapPlugin,
-        target_select::TargetSelectPlugin
+        target_select::TargetSelectPlugin,
+        lod::LodPlugin
+    ))
+    .add_plugins((
+        export::ExportPlugin,
+        snapshot::SnapshotPlugin,
+        session::SessionPlugin,
+        bookmarks::BookmarksPlugin,
+        history::HistoryPlugin,
+        minimap::MinimapPlugin,
+        inspector::InspectorPlugin,
+        palette::PalettePlugin,
+        dive::DivePlugin,
+        heights::HeightsPlugin
     ))
     .init_state::<GameState>()
     .add_systems(Update, check_ready.run_if(in_state(GameState::Loading)))
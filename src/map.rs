@@ -1,9 +1,10 @@
 use bevy::{
-    asset::RenderAssetUsages, input::keyboard::KeyboardInput, prelude::*, render::render_resource::{Extent3d, TextureDimension, TextureFormat}, ui::RelativeCursorPosition
+    asset::RenderAssetUsages, input::{keyboard::KeyboardInput, mouse::{MouseScrollUnit, MouseWheel}}, prelude::*, render::render_resource::{Extent3d, TextureDimension, TextureFormat}, ui::RelativeCursorPosition,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task}
 };
 
 use crate::{
-    camera::Cam, fractal::{calc_color, FractallBounds}, player::{Player, PlayerCell}, shared::{cell2xz, get_colorset, TILES_COUNT, VALLEY_SIZE}, GameState
+    camera::Cam, deep::ReferenceOrbit, export::ExportRequest, formula::Formula, fractal::{enter_julia, rebase_origin, update_orbit, DistanceShading, FractalView, FractallBounds, JuliaParent}, history::RecordView, player::{Player, PlayerCell}, palette::{Palette, PaletteCycle, PaletteLut}, shared::{cell2xz, TilesCount, TILES_COUNT, VALLEY_SIZE}, GameState
 };
 
 pub struct MapPlugin;
@@ -14,13 +15,28 @@ impl Plugin for MapPlugin {
         .add_systems(Update, toggle_map.run_if(on_event::<KeyboardInput>))
         .add_systems(OnEnter(GameState::Map), change_vis)
         .add_systems(OnExit(GameState::Map), change_vis)
-        .add_systems(Update, paint.run_if(resource_changed::<FractallBounds>))
+        .init_resource::<MapRender>()
+        .init_resource::<MapGesture>()
+        .add_systems(Update, wheel_zoom.before(rebase_origin).run_if(in_state(GameState::Map)).run_if(on_event::<MouseWheel>))
+        .add_systems(Update, paint.after(update_orbit).run_if(
+            resource_changed::<FractallBounds>
+            .or(resource_changed::<Formula>)
+            .or(resource_changed::<DistanceShading>)
+        ))
+        .add_systems(Update, receive_paint.after(paint))
+        .add_systems(Update, recolor.after(receive_paint).run_if(
+            resource_changed::<Palette>
+            .or(resource_changed::<PaletteCycle>)
+        ))
         ;
     }
 }
 
 // --
 const DEEP_RATIO: f64 = 10.;
+const ROWS_PER_JOB: u32 = 16;
+// coarse passes go first, each one refines the previous, a pan in progress only gets the first
+const PAINT_SCALES: [u32; 4] = [8, 4, 2, 1];
 
 #[derive(Component)]
 pub struct ValleyMap;
@@ -28,11 +44,46 @@ pub struct ValleyMap;
 #[derive(Component)]
 pub struct ValleyFrame;
 
+#[derive(Component)]
+pub struct ZoomRect;
+
+// pointer moves shorter than this are still clicks
+const DRAG_THRESHOLD: f32 = 4.;
+// zoom factor per wheel line
+const WHEEL_ZOOM: f64 = 1.2;
+
+// press position in map pixels, where a drag starts
+#[derive(Resource, Default)]
+pub struct MapGesture {
+    start: Vec2,
+    dragged: bool,
+    panning: bool
+}
+
 #[derive(Resource, Debug)]
-pub struct MapDim(u32, u32);
+pub struct MapDim(pub u32, pub u32);
 
 #[derive(Resource)]
-pub struct MapImage(Handle<Image>);
+pub struct MapImage(pub Handle<Image>);
+
+pub struct MapChunk {
+    rows: (u32, u32),
+    scale: u32,
+    values: Vec<f32>,
+    // in map pixels, empty without distance shading
+    distances: Vec<f32>
+}
+
+// dropping the tasks cancels the render in flight
+#[derive(Resource, Default)]
+pub struct MapRender {
+    tasks: Vec<Task<MapChunk>>,
+    // finest scale painted for each row block
+    painted: Vec<u32>,
+    // iteration value of every pixel, palette changes recolour from these
+    values: Vec<f32>,
+    distances: Vec<f32>
+}
 
 // --
 
@@ -123,28 +174,151 @@ pub struct MapImage(Handle<Image>);
 fn paint(
     bounds: Res<FractallBounds>,
     map_dim: Res<MapDim>,
-    mut images: ResMut<Assets<Image>>,
-    image_h: Res<MapImage>
+    formula: Res<Formula>,
+    orbit: Res<ReferenceOrbit>,
+    shading: Res<DistanceShading>,
+    gesture: Res<MapGesture>,
+    mut render: ResMut<MapRender>
+) {
+    let step = (
+        (bounds.x.1 - bounds.x.0) / map_dim.0 as f64,
+        (bounds.y.1 - bounds.y.0) / map_dim.1 as f64
+    );
+    let start = (bounds.x.0, bounds.y.0);
+    let width = map_dim.0;
+    let view = FractalView::new(&formula, &bounds, &orbit);
+    let pool = AsyncComputeTaskPool::get();
+    let with_distance = shading.enabled;
+
+    render.tasks.clear();
+    render.painted = vec![u32::MAX; map_dim.1.div_ceil(ROWS_PER_JOB) as usize];
+    render.values.resize((map_dim.0 * map_dim.1) as usize, 0.);
+    render.distances = if with_distance {vec![0.; (map_dim.0 * map_dim.1) as usize]} else {Vec::new()};
+    let scales = if gesture.panning {&PAINT_SCALES[.. 1]} else {&PAINT_SCALES[..]};
+    for &scale in scales {
+        for row in (0 .. map_dim.1).step_by(ROWS_PER_JOB as usize) {
+            let rows = (row, (row + ROWS_PER_JOB).min(map_dim.1));
+            let view = view.clone();
+            render.tasks.push(pool.spawn(async move {
+                paint_chunk(&view, start, step, width, rows, scale, with_distance)
+            }));
+        }
+    }
+}
+
+// ---
+
+fn paint_chunk(
+    view: &FractalView,
+    start: (f64, f64),
+    step: (f64, f64),
+    width: u32,
+    rows: (u32, u32),
+    scale: u32,
+    with_distance: bool
+) -> MapChunk {
+    let len = ((rows.1 - rows.0) * width) as usize;
+    let mut values = vec![0.; len];
+    let mut distances = if with_distance {vec![0.; len]} else {Vec::new()};
+    for j in (rows.0 .. rows.1).step_by(scale as usize) {
+        for i in (0 .. width).step_by(scale as usize) {
+            let p = (start.0 + i as f64 * step.0, start.1 + j as f64 * step.1);
+            let (value, distance) = if with_distance {
+                let (value, distance) = view.calc_color_distance(p.0, p.1);
+                (value, (distance / step.0) as f32)
+            } else {
+                (view.calc_color(p.0, p.1), 0.)
+            };
+            for pj in j .. (j + scale).min(rows.1) {
+                for pi in i .. (i + scale).min(width) {
+                    let index = ((pj - rows.0) * width + pi) as usize;
+                    values[index] = value;
+                    if with_distance {
+                        distances[index] = distance;
+                    }
+                }
+            }
+        }
+    }
+    MapChunk{rows, scale, values, distances}
+}
+
+// ---
+
+// distances are empty without distance shading
+fn color_pixels(pixels: &mut [u8], values: &[f32], distances: &[f32], lut: &PaletteLut) {
+    if distances.is_empty() {
+        for (pixel, value) in pixels.chunks_exact_mut(4).zip(values) {
+            pixel.copy_from_slice(&lut.rgba(*value));
+        }
+        return;
+    }
+    for ((pixel, value), distance) in pixels.chunks_exact_mut(4).zip(values).zip(distances) {
+        pixel.copy_from_slice(&lut.shaded(*value, *distance));
+    }
+}
+
+// ---
 
+fn receive_paint(
+    mut render: ResMut<MapRender>,
+    mut images: ResMut<Assets<Image>>,
+    image_h: Res<MapImage>,
+    palette: Res<Palette>,
+    cycle: Res<PaletteCycle>
 ) {
-    let step_x = (bounds.x.1 - bounds.x.0) / map_dim.0 as f64;
-    let step_y =  (bounds.y.1 - bounds.y.0) / map_dim.1 as f64;
-    let x0 = bounds.x.0 as f64;
-    let y0 = bounds.y.0 as f64;
-    let mut x = x0;
-    let mut y = y0;
-    let image = images.get_mut(&image_h.0).unwrap();
-    let colorset = get_colorset();
-    for i in 0 .. map_dim.0 {
-        for j in 0 .. map_dim.1 {
-            image.set_color_at(i, j, colorset[calc_color(x, y)].with_alpha(1.)).expect("Error");
-            y += step_y; 
+    if render.tasks.is_empty() {
+        return;
+    }
+    let mut chunks = Vec::new();
+    render.tasks.retain_mut(|task| {
+        match block_on(future::poll_once(task)) {
+            Some(chunk) => {
+                chunks.push(chunk);
+                false
+            },
+            None => true
+        }
+    });
+    if chunks.is_empty() {
+        return;
+    }
+    let Some(image) = images.get_mut(&image_h.0) else {
+        return;
+    };
+    let lut = palette.cycled(&cycle).lut();
+    for chunk in chunks {
+        let block = (chunk.rows.0 / ROWS_PER_JOB) as usize;
+        if chunk.scale > render.painted[block] {
+            continue;
         }
-        x += step_x;
-        y = y0;
-    } 
+        render.painted[block] = chunk.scale;
+        let from = (chunk.rows.0 * image.width()) as usize;
+        let to = from + chunk.values.len();
+        render.values[from .. to].copy_from_slice(&chunk.values);
+        if !chunk.distances.is_empty() {
+            render.distances[from .. to].copy_from_slice(&chunk.distances);
+        }
+        color_pixels(&mut image.data[from * 4 .. to * 4], &chunk.values, &chunk.distances, &lut);
+    }
+}
 
-} 
+// ---
+
+fn recolor(
+    render: Res<MapRender>,
+    mut images: ResMut<Assets<Image>>,
+    image_h: Res<MapImage>,
+    palette: Res<Palette>,
+    cycle: Res<PaletteCycle>
+) {
+    let Some(image) = images.get_mut(&image_h.0) else {
+        return;
+    };
+    if render.values.len() * 4 == image.data.len() {
+        color_pixels(&mut image.data, &render.values, &render.distances, &palette.cycled(&cycle).lut());
+    }
+}
 
 // ---
 
@@ -190,8 +364,22 @@ fn startup(
         RelativeCursorPosition::default()
     ))
     .observe(on_click)
+    .observe(on_press)
+    .observe(on_drag)
+    .observe(on_drag_end)
     .with_children(|parent| {
-        let size = w_width as f32 / TILES_COUNT as f32;
+        parent.spawn((
+            ZoomRect,
+            Node {
+                position_type: PositionType::Absolute,
+                border: UiRect::all(Val::Px(1.)),
+                display: Display::None,
+                ..default()
+            },
+            ZIndex(11),
+            BorderColor(Color::WHITE)
+        ));
+        let size = w_width as f32 * TILES_COUNT as f32 / VALLEY_SIZE as f32;
         parent.spawn(
             (
                 ValleyFrame,
@@ -233,6 +421,7 @@ fn toggle_map(
 
 // ---
 
+#[allow(clippy::too_many_arguments)]
 fn on_click(
     click: Trigger<Pointer<Click>>,
     map_q: Single<&RelativeCursorPosition, With<ValleyMap>>,
@@ -241,15 +430,31 @@ fn on_click(
     mut next: ResMut<NextState<GameState>>,
     cam_q: Single<&mut Transform, (With<Cam>, Without<Player>)>,
     mut bounds: ResMut<FractallBounds>,
-    keys: Res<ButtonInput<KeyCode>>
+    keys: Res<ButtonInput<KeyCode>>,
+    mut formula: ResMut<Formula>,
+    mut parent: ResMut<JuliaParent>,
+    gesture: Res<MapGesture>,
+    mut rw: EventWriter<RecordView>
 ) {
+    if gesture.dragged {
+        return;
+    }
     let rcp = map_q.into_inner();
     if let Some(v) = rcp.normalized {
         match click.event().button {
+            PointerButton::Primary if keys.pressed(KeyCode::ControlLeft) => {
+                let origin = bounds.origin_f64();
+                let c = (
+                    origin.0 + bounds.x.0 + (bounds.x.1 - bounds.x.0) * v.x as f64,
+                    origin.1 + bounds.y.0 + (bounds.y.1 - bounds.y.0) * v.y as f64
+                );
+                enter_julia(c, &mut formula, &mut bounds, &mut parent);
+                rw.send_default();
+            },
             PointerButton::Primary => {
                 let cell = (
-                    (VALLEY_SIZE as f32 * v.x).round() as usize, 
-                    (VALLEY_SIZE as f32 * v.y).round() as usize
+                    (VALLEY_SIZE as f32 * v.x).round() as i32, 
+                    (VALLEY_SIZE as f32 * v.y).round() as i32
                 );
                 let mut pt = player_q.into_inner();
                 let pos = cell2xz(cell);
@@ -287,12 +492,13 @@ fn on_click(
                         center.1 + 0.5 * map_dim.1 as f64  * step.1
                     );
                 } else {
-                    let frame_len = (map_dim.0 as f64 / DEEP_RATIO, map_dim.0 as f64 / DEEP_RATIO);
-                    bounds.x.0 += step.0 * (center_cell.0 as f64 - frame_len.0 * 0.5) as f64; 
+                    let frame_len = (map_dim.0 as f64 / DEEP_RATIO, map_dim.1 as f64 / DEEP_RATIO);
+                    bounds.x.0 += step.0 * (center_cell.0 as f64 - frame_len.0 * 0.5); 
                     bounds.x.1 = bounds.x.0 + step.0 * frame_len.0; 
-                    bounds.y.0 += step.1 * (center_cell.1 as f64 - frame_len.1 * 0.5) as f64; 
+                    bounds.y.0 += step.1 * (center_cell.1 as f64 - frame_len.1 * 0.5); 
                     bounds.y.1 = bounds.y.0 + step.1 * frame_len.1; 
                 }
+                rw.send_default();
             }
             _ => ()
         }
@@ -302,12 +508,164 @@ fn on_click(
 }
 
 
+// ---
+
+// the drag only starts past the picking threshold, the rectangle starts where the button went down
+fn on_press(
+    _trg: Trigger<Pointer<Down>>,
+    map_q: Single<&RelativeCursorPosition, With<ValleyMap>>,
+    map_dim: Res<MapDim>,
+    mut gesture: ResMut<MapGesture>
+) {
+    gesture.dragged = false;
+    if let Some(v) = map_q.into_inner().normalized {
+        gesture.start = v * Vec2::new(map_dim.0 as f32, map_dim.1 as f32);
+    }
+}
+
+// ---
+
+// the rectangle keeps the map aspect ratio, the longer side of the drag wins
+fn zoom_rect(start: Vec2, distance: Vec2, map_dim: &MapDim) -> Rect {
+    let aspect = map_dim.0 as f32 / map_dim.1 as f32;
+    let w = distance.x.abs().max(distance.y.abs() * aspect);
+    let size = Vec2::new(w.copysign(distance.x), (w / aspect).copysign(distance.y));
+    Rect::from_corners(start, start + size)
+}
+
+// ---
+
+fn on_drag(
+    trg: Trigger<Pointer<Drag>>,
+    map_dim: Res<MapDim>,
+    mut gesture: ResMut<MapGesture>,
+    mut bounds: ResMut<FractallBounds>,
+    rect_q: Single<&mut Node, With<ZoomRect>>,
+    keys: Res<ButtonInput<KeyCode>>
+) {
+    let drag = trg.event();
+    if drag.distance.length() < DRAG_THRESHOLD && !gesture.dragged {
+        return;
+    }
+    gesture.dragged = true;
+    match drag.button {
+        PointerButton::Primary if !keys.pressed(KeyCode::ControlLeft) => {
+            let r = if keys.pressed(KeyCode::AltLeft) {
+                Rect::from_corners(gesture.start, gesture.start + drag.distance)
+            } else {
+                zoom_rect(gesture.start, drag.distance, &map_dim)
+            };
+            let mut node = rect_q.into_inner();
+            node.display = Display::Flex;
+            node.left = Val::Px(r.min.x);
+            node.top = Val::Px(r.min.y);
+            node.width = Val::Px(r.width());
+            node.height = Val::Px(r.height());
+        },
+        PointerButton::Middle => {
+            gesture.panning = true;
+            let step = (
+                (bounds.x.1 - bounds.x.0) / map_dim.0 as f64,
+                (bounds.y.1 - bounds.y.0) / map_dim.1 as f64
+            );
+            let shift = (drag.delta.x as f64 * step.0, drag.delta.y as f64 * step.1);
+            bounds.x = (bounds.x.0 - shift.0, bounds.x.1 - shift.0);
+            bounds.y = (bounds.y.0 - shift.1, bounds.y.1 - shift.1);
+        },
+        _ => ()
+    }
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn on_drag_end(
+    trg: Trigger<Pointer<DragEnd>>,
+    map_dim: Res<MapDim>,
+    mut gesture: ResMut<MapGesture>,
+    mut bounds: ResMut<FractallBounds>,
+    rect_q: Single<&mut Node, With<ZoomRect>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut ew: EventWriter<ExportRequest>,
+    mut rw: EventWriter<RecordView>
+) {
+    let drag = trg.event();
+    // a pan is one history entry wherever it ends up, and the view it stops at gets the fine passes
+    if drag.button == PointerButton::Middle && gesture.dragged {
+        gesture.panning = false;
+        bounds.set_changed();
+        rw.send_default();
+        return;
+    }
+    let mut node = rect_q.into_inner();
+    if node.display == Display::None {
+        return;
+    }
+    node.display = Display::None;
+    if drag.button != PointerButton::Primary || !gesture.dragged {
+        return;
+    }
+    // the map spans the whole valley grid, the free rectangle goes to the export in cells
+    if keys.pressed(KeyCode::AltLeft) {
+        let r = Rect::from_corners(gesture.start, gesture.start + drag.distance);
+        let cell = |px: f32, dim: u32| (px / dim as f32 * VALLEY_SIZE as f32).round().clamp(0., VALLEY_SIZE as f32) as i32;
+        let region = (
+            (cell(r.min.x, map_dim.0), cell(r.max.x, map_dim.0)),
+            (cell(r.min.y, map_dim.1), cell(r.max.y, map_dim.1))
+        );
+        if region.0.0 < region.0.1 && region.1.0 < region.1.1 {
+            ew.send(ExportRequest{region: Some(region)});
+        }
+        return;
+    }
+    let r = zoom_rect(gesture.start, drag.distance, &map_dim);
+    let step = (
+        (bounds.x.1 - bounds.x.0) / map_dim.0 as f64,
+        (bounds.y.1 - bounds.y.0) / map_dim.1 as f64
+    );
+    let x0 = bounds.x.0;
+    let y0 = bounds.y.0;
+    bounds.x = (x0 + r.min.x as f64 * step.0, x0 + r.max.x as f64 * step.0);
+    bounds.y = (y0 + r.min.y as f64 * step.1, y0 + r.max.y as f64 * step.1);
+    rw.send_default();
+}
+
+// ---
+
+fn wheel_zoom(
+    mut er: EventReader<MouseWheel>,
+    map_q: Single<&RelativeCursorPosition, With<ValleyMap>>,
+    mut bounds: ResMut<FractallBounds>,
+    mut rw: EventWriter<RecordView>
+) {
+    let rcp = map_q.into_inner();
+    let Some(v) = rcp.normalized.filter(|_| rcp.mouse_over()) else {
+        return;
+    };
+    let lines: f32 = er.read().map(|e| match e.unit {
+        MouseScrollUnit::Line => e.y,
+        MouseScrollUnit::Pixel => e.y / 100.
+    }).sum();
+    if lines == 0. {
+        return;
+    }
+    // the point under the cursor stays put
+    let f = WHEEL_ZOOM.powf(-lines as f64);
+    let c = (
+        bounds.x.0 + (bounds.x.1 - bounds.x.0) * v.x as f64,
+        bounds.y.0 + (bounds.y.1 - bounds.y.0) * v.y as f64
+    );
+    bounds.zoom_at(c, f);
+    rw.send_default();
+}
+
 // ---
 
 fn change_vis(
     vis_q: Single<&mut Visibility, With<ValleyMap>>,
     player_cell: Res<PlayerCell>,
     map_dim: Res<MapDim>,
+    tiles: Res<TilesCount>,
     frame_q: Single<&mut Node, With<ValleyFrame>>
 ) {
     let mut vis = vis_q.into_inner();
@@ -317,9 +675,11 @@ fn change_vis(
         *vis = Visibility::Visible;
         let x = player_cell.0 as f32 * (map_dim.0 as f32/ VALLEY_SIZE as f32);
         let y = player_cell.1 as f32 * (map_dim.1 as f32/ VALLEY_SIZE as f32);
-        let frame_size = map_dim.0 as f32 / TILES_COUNT as f32;
+        let frame_size = map_dim.0 as f32 * tiles.0 as f32 / VALLEY_SIZE as f32;
         let mut node = frame_q.into_inner();
         node.left = Val::Px(x - 0.5 * frame_size);
         node.top = Val::Px(y - 0.5 * frame_size);
+        node.width = Val::Px(frame_size);
+        node.height = Val::Px(frame_size);
     }
 }
@@ -0,0 +1,233 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    input::keyboard::KeyboardInput,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat}
+};
+
+use crate::{
+    bookmarks::Bookmarks,
+    formula::Formula,
+    fractal::{DistanceShading, FractallBounds, FractallCollors},
+    map::{MapDim, MapImage},
+    palette::{Palette, PaletteCycle},
+    player::Player,
+    shared::{CELL_SIZE, VALLEY_SIZE},
+    GameState
+};
+
+pub struct MinimapPlugin;
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<MinimapSettings>()
+        .add_systems(Startup, startup)
+        .add_systems(Update, minimap_keys.run_if(on_event::<KeyboardInput>))
+        .add_systems(Update, change_vis.after(minimap_keys).run_if(
+            state_changed::<GameState>
+            .or(resource_changed::<MinimapSettings>)
+        ))
+        .add_systems(Update, draw.run_if(in_state(GameState::Game)))
+        ;
+    }
+}
+
+// ---
+
+const MINIMAP_SIZE: u32 = 200;
+// valley cells per minimap pixel
+const MINIMAP_ZOOMS: [f32; 5] = [1., 2., 4., 8., 16.];
+
+#[derive(Resource, Debug)]
+pub struct MinimapSettings {
+    pub visible: bool,
+    pub zoom: f32,
+    // player forward always points up
+    pub rotate: bool
+}
+
+impl Default for MinimapSettings {
+    fn default() -> Self {
+        Self {visible: true, zoom: 4., rotate: false}
+    }
+}
+
+#[derive(Component)]
+pub struct Minimap;
+
+#[derive(Resource)]
+pub struct MinimapImage(Handle<Image>);
+
+// ---
+
+fn startup(
+    mut cmd: Commands,
+    mut images: ResMut<Assets<Image>>
+) {
+    let image_h = images.add(Image::new_fill(
+        Extent3d {width: MINIMAP_SIZE, height: MINIMAP_SIZE, depth_or_array_layers: 1},
+        TextureDimension::D2,
+        &Srgba::BLACK.to_u8_array(),
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::all()
+    ));
+    cmd.insert_resource(MinimapImage(image_h.clone()));
+    cmd.spawn((
+        Minimap,
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.),
+            bottom: Val::Px(10.),
+            width: Val::Px(MINIMAP_SIZE as f32),
+            height: Val::Px(MINIMAP_SIZE as f32),
+            border: UiRect::all(Val::Px(1.)),
+            ..default()
+        },
+        BorderColor(Color::WHITE),
+        ImageNode::new(image_h),
+        Visibility::Hidden
+    ));
+}
+
+// ---
+
+fn minimap_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<MinimapSettings>
+) {
+    if !keys.just_pressed(KeyCode::KeyN) {
+        return;
+    }
+    if keys.pressed(KeyCode::ShiftLeft) {
+        settings.rotate = !settings.rotate;
+    } else if keys.pressed(KeyCode::ControlLeft) {
+        let i = MINIMAP_ZOOMS.iter().position(|z| *z == settings.zoom).unwrap_or(0);
+        settings.zoom = MINIMAP_ZOOMS[(i + 1) % MINIMAP_ZOOMS.len()];
+    } else {
+        settings.visible = !settings.visible;
+    }
+}
+
+// ---
+
+fn change_vis(
+    settings: Res<MinimapSettings>,
+    state: Res<State<GameState>>,
+    vis_q: Single<&mut Visibility, With<Minimap>>
+) {
+    *vis_q.into_inner() = if settings.visible && *state == GameState::Game {Visibility::Visible} else {Visibility::Hidden};
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn draw(
+    settings: Res<MinimapSettings>,
+    p_q: Single<&Transform, With<Player>>,
+    colors: Res<FractallCollors>,
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    bookmarks: Res<Bookmarks>,
+    palette: Res<Palette>,
+    cycle: Res<PaletteCycle>,
+    shading: Res<DistanceShading>,
+    map_dim: Res<MapDim>,
+    map_image: Res<MapImage>,
+    minimap_image: Res<MinimapImage>,
+    mut images: ResMut<Assets<Image>>
+) {
+    if !settings.visible {
+        return;
+    }
+    let t = p_q.into_inner();
+    // player position in cells, not rounded so the map slides smoothly
+    let half_valley = (VALLEY_SIZE as f32 * 0.5).floor();
+    let center = Vec2::new(half_valley + t.translation.x / CELL_SIZE, half_valley + t.translation.z / CELL_SIZE);
+    let forward = Vec2::new(t.forward().x, t.forward().z).normalize_or(Vec2::NEG_Y);
+    // minimap pixel offset to cell offset, up is the player forward when rotating
+    let (c, s) = if settings.rotate {(-forward.y, forward.x)} else {(1., 0.)};
+    let to_cell = |d: Vec2| center + Vec2::new(c * d.x - s * d.y, s * d.x + c * d.y) * settings.zoom;
+    let to_pixel = |cell: Vec2| {
+        let d = (cell - center) / settings.zoom;
+        Vec2::new(c * d.x + s * d.y, -s * d.x + c * d.y) + Vec2::splat(MINIMAP_SIZE as f32 * 0.5)
+    };
+
+    let Some(map) = images.get(&map_image.0) else {
+        return;
+    };
+    let lut = palette.cycled(&cycle).lut();
+    let window = colors.range();
+    let size = MINIMAP_SIZE as usize;
+    let mut pixels = vec![0u8; size * size * 4];
+    for v in 0 .. size {
+        for u in 0 .. size {
+            let cell = to_cell(Vec2::new(u as f32, v as f32) - Vec2::splat(size as f32 * 0.5));
+            let index = (v * size + u) * 4;
+            let cell_i = (cell.x.floor() as i32, cell.y.floor() as i32);
+            // loaded tiles are sharper than the map image
+            let in_window = window.is_some_and(|w| (w.0.0 .. w.0.1).contains(&cell_i.0) && (w.1.0 .. w.1.1).contains(&cell_i.1));
+            if in_window {
+                let rgba = if shading.enabled {lut.shaded(colors.get(cell_i), colors.distance(cell_i))} else {lut.rgba(colors.get(cell_i))};
+                pixels[index .. index + 4].copy_from_slice(&rgba);
+            } else if cell.x < 0. || cell.y < 0. || cell.x >= VALLEY_SIZE as f32 || cell.y >= VALLEY_SIZE as f32 {
+                pixels[index + 3] = 255;
+            } else {
+                let mx = (cell.x / VALLEY_SIZE as f32 * map_dim.0 as f32) as usize;
+                let my = (cell.y / VALLEY_SIZE as f32 * map_dim.1 as f32) as usize;
+                let from = (my.min(map_dim.1 as usize - 1) * map_dim.0 as usize + mx.min(map_dim.0 as usize - 1)) * 4;
+                pixels[index .. index + 4].copy_from_slice(&map.data[from .. from + 4]);
+            }
+        }
+    }
+
+    let mut plot = |p: Vec2, color: [u8; 4]| {
+        if p.x >= 0. && p.y >= 0. && p.x < size as f32 && p.y < size as f32 {
+            let index = (p.y as usize * size + p.x as usize) * 4;
+            pixels[index .. index + 4].copy_from_slice(&color);
+        }
+    };
+
+    // loaded tiles frame
+    if let Some(w) = window {
+        let corners = [
+            Vec2::new(w.0.0 as f32, w.1.0 as f32), Vec2::new(w.0.1 as f32, w.1.0 as f32),
+            Vec2::new(w.0.1 as f32, w.1.1 as f32), Vec2::new(w.0.0 as f32, w.1.1 as f32)
+        ].map(to_pixel);
+        for k in 0 .. 4 {
+            let (a, b) = (corners[k], corners[(k + 1) % 4]);
+            let steps = a.distance(b).ceil().max(1.) as usize;
+            for i in 0 ..= steps {
+                plot(a.lerp(b, i as f32 / steps as f32), [255, 255, 255, 255]);
+            }
+        }
+    }
+
+    // bookmarks made in this view
+    for b in bookmarks.0.iter().filter(|b| b.formula.index == formula.index && b.formula.julia == formula.julia && b.bounds.same_view(&bounds)) {
+        let p = to_pixel(Vec2::new(b.cell.0 as f32, b.cell.1 as f32));
+        for dy in -2 ..= 2 {
+            for dx in -2 ..= 2 {
+                plot(p + Vec2::new(dx as f32, dy as f32), [255, 220, 0, 255]);
+            }
+        }
+    }
+
+    // player arrow
+    let dir = if settings.rotate {Vec2::NEG_Y} else {forward};
+    let mid = Vec2::splat(size as f32 * 0.5);
+    let (tip, left, right) = (mid + dir * 7., mid - dir * 4. + dir.perp() * 4., mid - dir * 4. - dir.perp() * 4.);
+    let edge = |a: Vec2, b: Vec2, p: Vec2| (b - a).perp_dot(p - a);
+    for y in -8 ..= 8 {
+        for x in -8 ..= 8 {
+            let p = mid + Vec2::new(x as f32, y as f32) + Vec2::splat(0.5);
+            let (e0, e1, e2) = (edge(tip, left, p), edge(left, right, p), edge(right, tip, p));
+            if (e0 >= 0. && e1 >= 0. && e2 >= 0.) || (e0 <= 0. && e1 <= 0. && e2 <= 0.) {
+                plot(p, [255, 0, 0, 255]);
+            }
+        }
+    }
+
+    if let Some(image) = images.get_mut(&minimap_image.0) {
+        image.data = pixels;
+    }
+}
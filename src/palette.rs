@@ -0,0 +1,741 @@
+use std::{f32::consts::TAU, path::Path};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    core_pipeline::bloom::Bloom,
+    input::keyboard::KeyboardInput,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat}
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::Cam,
+    formula::INTERIOR_SPAN,
+    fractal::DistanceShading,
+    session::{read_text, write_text}
+};
+
+pub struct PalettePlugin;
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .insert_resource(Palette::from_args())
+        .init_resource::<PaletteEdit>()
+        .init_resource::<PaletteCycle>()
+        .add_systems(Startup, startup)
+        .add_systems(Update, palette_keys.run_if(on_event::<KeyboardInput>))
+        .add_systems(Update, cycle.after(palette_keys).run_if(|c: Res<PaletteCycle>| c.running || c.pulse))
+        .add_systems(Update, import_dropped.run_if(on_event::<FileDragAndDrop>))
+        .add_systems(Update, update_bloom.after(palette_keys).run_if(
+            resource_changed::<PaletteCycle>
+            .or(resource_changed::<DistanceShading>)
+        ))
+        .add_systems(Update, rebuild_panel.after(palette_keys).after(import_dropped).run_if(
+            resource_changed::<Palette>
+            .or(resource_changed::<PaletteEdit>)
+        ))
+        ;
+    }
+}
+
+// ---
+
+// file name on desktop, local storage key on wasm
+const PALETTE_FILE: &str = "palette.txt";
+// entries per gradient cycle in the lookup table
+const LUT_SIZE: usize = 1024;
+const PREVIEW_WIDTH: u32 = 256;
+// ultra fractal gradients run from index 0 to 399
+const UGR_SPAN: f32 = 400.;
+// brightest pulse, hdr colours above 1 glow through bloom
+const PULSE_GAIN: f32 = 3.;
+// pulse waves per second along the iteration index
+const PULSE_SPEED: f32 = 0.5;
+// distance shading, brightest lift at the boundary and its falloff in cells or map pixels
+const GLOW_GAIN: f32 = 2.;
+const GLOW_WIDTH: f32 = 4.;
+// map pixels closer to the set than this are drawn as filament outlines
+const OUTLINE_WIDTH: f32 = 1.;
+const OUTLINE: [u8; 4] = [255, 255, 255, 255];
+// interior colours are the gradient this far towards it from the inside colour, so the set still reads as the set
+const INTERIOR_MIX: f32 = 0.6;
+// terrace colour steps over the interior span
+const INTERIOR_BANDS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stop {
+    pub pos: f32,
+    pub color: Srgba
+}
+
+#[derive(Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    // sorted by position in 0..1, the gradient wraps from the last stop to the first
+    pub stops: Vec<Stop>,
+    // colour of points that never escape
+    pub inside: Srgba,
+    // iterations per gradient cycle
+    pub length: usize,
+    // gradient position of the first iteration
+    pub offset: f32,
+    // cycles per second while cycling, the sign is the direction
+    pub speed: f32
+}
+
+// the shift runs at the palette speed on top of its offset, the pulse is a brightness wave along the iteration index,
+// both stay out of the palette so a saved one is never caught mid cycle
+#[derive(Resource, Debug, Default)]
+pub struct PaletteCycle {
+    pub running: bool,
+    pub pulse: bool,
+    pub shift: f32,
+    pub phase: f32
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::presets().remove(0)
+    }
+}
+
+// colours for every iteration value, cheap enough to recolour the whole map each frame
+pub struct PaletteLut {
+    inside: [u8; 4],
+    table: Vec<[u8; 4]>,
+    length: f32,
+    offset: f32
+}
+
+impl PaletteLut {
+    pub fn rgba(&self, value: f32) -> [u8; 4] {
+        if value < 0. {
+            let t = (-value / INTERIOR_SPAN + self.offset).rem_euclid(1.);
+            let color = self.table[((t * LUT_SIZE as f32) as usize).min(LUT_SIZE - 1)];
+            return std::array::from_fn(|k| (self.inside[k] as f32 + (color[k] as f32 - self.inside[k] as f32) * INTERIOR_MIX) as u8);
+        }
+        if value <= 0. {
+            return self.inside;
+        }
+        let t = ((value.max(1.) - 1.) / self.length + self.offset).rem_euclid(1.);
+        let color = self.table[((t * LUT_SIZE as f32) as usize).min(LUT_SIZE - 1)];
+        if value >= 1. {
+            return color;
+        }
+        // smooth values below the first iteration fade in from the inside colour
+        std::array::from_fn(|k| (self.inside[k] as f32 + (color[k] as f32 - self.inside[k] as f32) * value) as u8)
+    }
+
+    // with the boundary glow, escaping pixels too close to the set to resolve become outlines
+    pub fn shaded(&self, value: f32, distance: f32) -> [u8; 4] {
+        let color = self.rgba(value);
+        if value <= 0. {
+            return color;
+        }
+        if distance < OUTLINE_WIDTH {
+            return OUTLINE;
+        }
+        let t = glow(distance) / GLOW_GAIN;
+        std::array::from_fn(|k| if k == 3 {color[k]} else {(color[k] as f32 + (255. - color[k] as f32) * t) as u8})
+    }
+}
+
+// ---
+
+// extra brightness of a point outside the set, by its distance from the boundary
+pub fn glow(distance: f32) -> f32 {
+    GLOW_GAIN * (-distance.max(0.) / GLOW_WIDTH).exp()
+}
+
+// linear terrace colour lifted along the boundary, above 1 it glows through bloom
+pub fn glow_tile(color: [f32; 4], value: f32, distance: f32) -> [f32; 4] {
+    if value <= 0. {
+        return color;
+    }
+    let gain = 1. + glow(distance);
+    [color[0] * gain, color[1] * gain, color[2] * gain, color[3]]
+}
+
+// selected stop in the editor and the preset last picked
+#[derive(Resource, Default)]
+pub struct PaletteEdit {
+    stop: usize,
+    preset: usize
+}
+
+#[derive(Component)]
+pub struct PalettePanel;
+
+#[derive(Resource)]
+pub struct PalettePreview(Handle<Image>);
+
+#[derive(Component, Clone, Copy)]
+pub enum PaletteButton {
+    Preset(i32),
+    Select(i32),
+    AddStop,
+    DeleteStop,
+    Move(f32),
+    Hue(f32),
+    Saturation(f32),
+    Lightness(f32),
+    Length(i32),
+    Offset(f32),
+    Speed(f32),
+    Save,
+    Load
+}
+
+// ---
+
+impl Palette {
+    fn new(name: &str, length: usize, stops: &[(f32, Srgba)]) -> Self {
+        Self {
+            name: name.to_string(),
+            stops: stops.iter().map(|(pos, color)| Stop {pos: *pos, color: *color}).collect(),
+            inside: Srgba::BLACK,
+            length,
+            offset: 0.,
+            speed: 0.25
+        }
+    }
+
+    pub fn presets() -> Vec<Self> {
+        // the nine hues the valley always had
+        let valley: Vec<(f32, Srgba)> = [
+            (209., 1.), (252., 0.854), (203., 0.955), (132., 0.898), (123., 0.982),
+            (279., 1.), (36., 1.), (4., 0.86), (0., 1.)
+        ].iter().enumerate()
+        .map(|(i, (h, s))| (i as f32 / 9., Srgba::from(Hsla::hsl(*h, *s, 0.45))))
+        .collect();
+        let rainbow: Vec<(f32, Srgba)> = (0 .. 6)
+        .map(|i| (i as f32 / 6., Srgba::from(Hsla::hsl(i as f32 * 60., 1., 0.5))))
+        .collect();
+        vec![
+            Self::new("valley", 9, &valley),
+            Self::new("classic", 64, &[
+                (0., Srgba::rgb_u8(0, 7, 100)),
+                (0.16, Srgba::rgb_u8(32, 107, 203)),
+                (0.42, Srgba::rgb_u8(237, 255, 255)),
+                (0.6425, Srgba::rgb_u8(255, 170, 0)),
+                (0.8575, Srgba::rgb_u8(0, 2, 0))
+            ]),
+            Self::new("grayscale", 32, &[
+                (0., Srgba::rgb_u8(32, 32, 32)),
+                (0.5, Srgba::WHITE)
+            ]),
+            Self::new("fire", 48, &[
+                (0., Srgba::rgb_u8(32, 0, 0)),
+                (0.25, Srgba::rgb_u8(128, 0, 0)),
+                (0.5, Srgba::rgb_u8(255, 64, 0)),
+                (0.75, Srgba::rgb_u8(255, 192, 0)),
+                (0.9, Srgba::rgb_u8(255, 255, 160))
+            ]),
+            Self::new("ocean", 48, &[
+                (0., Srgba::rgb_u8(0, 10, 40)),
+                (0.3, Srgba::rgb_u8(0, 78, 146)),
+                (0.6, Srgba::rgb_u8(0, 180, 216)),
+                (0.8, Srgba::rgb_u8(202, 240, 248))
+            ]),
+            Self::new("rainbow", 36, &rainbow)
+        ]
+    }
+
+    // fractal-valley --palette <file>
+    fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let Some(path) = args.iter().position(|a| a == "--palette").and_then(|i| args.get(i + 1)) else {
+            return Self::default();
+        };
+        Self::load(path).unwrap_or_else(|e| {
+            warn!("palette {} not loaded: {}", path, e);
+            Self::default()
+        })
+    }
+
+    // colour at a position of the gradient cycle
+    pub fn gradient(&self, t: f32) -> Srgba {
+        let (Some(first), Some(last)) = (self.stops.first(), self.stops.last()) else {
+            return self.inside;
+        };
+        let next = self.stops.iter().position(|s| s.pos > t);
+        let (a, b) = match next {
+            Some(0) => (Stop {pos: last.pos - 1., ..*last}, *first),
+            Some(i) => (self.stops[i - 1], self.stops[i]),
+            None => (*last, Stop {pos: first.pos + 1., ..*first})
+        };
+        let span = b.pos - a.pos;
+        if span <= 0. {
+            return a.color;
+        }
+        a.color.mix(&b.color, (t - a.pos) / span)
+    }
+
+    pub fn color(&self, value: f32) -> Color {
+        if value < 0. {
+            let interior = self.gradient((-value / INTERIOR_SPAN + self.offset).rem_euclid(1.));
+            return self.inside.mix(&interior, INTERIOR_MIX).into();
+        }
+        let escaped = self.gradient(((value.max(1.) - 1.) / self.length as f32 + self.offset).rem_euclid(1.));
+        if value >= 1. {
+            escaped.into()
+        } else {
+            self.inside.mix(&escaped, value.max(0.)).into()
+        }
+    }
+
+    // flat colour steps for the terraces, 0 is inside, the interior measures follow the escape bands
+    pub fn band(&self, value: f32) -> usize {
+        if value < 0. {
+            let step = ((-value / INTERIOR_SPAN).rem_euclid(1.) * INTERIOR_BANDS as f32) as usize;
+            return self.length + 1 + step.min(INTERIOR_BANDS - 1);
+        }
+        if value < 1. {0} else {1 + (value as usize - 1) % self.length}
+    }
+
+    pub fn bands(&self) -> usize {
+        self.length + 1 + INTERIOR_BANDS
+    }
+
+    pub fn band_color(&self, band: usize) -> Color {
+        if band > self.length {
+            return self.color(-((band - self.length - 1) as f32 * INTERIOR_SPAN / INTERIOR_BANDS as f32).max(1e-3));
+        }
+        self.color(band as f32)
+    }
+
+    // the palette as it is shown, with the cycling shift added to the offset
+    pub fn cycled(&self, cycle: &PaletteCycle) -> Self {
+        Self {
+            offset: (self.offset + cycle.shift).rem_euclid(1.),
+            ..self.clone()
+        }
+    }
+
+    // linear colour of every band for the terraces
+    pub fn tile_colors(&self, cycle: &PaletteCycle) -> Vec<[f32; 4]> {
+        let shown = self.cycled(cycle);
+        (0 .. self.bands()).map(|band| {
+            let gain = if cycle.pulse && band > 0 && band <= self.length {
+                let t = (band as f32 - 1.) / self.length as f32 - cycle.phase;
+                1. + PULSE_GAIN * (0.5 + 0.5 * (t * TAU).cos()).powi(8)
+            } else {
+                1.
+            };
+            (LinearRgba::from(shown.band_color(band)) * gain).with_alpha(1.).to_f32_array()
+        })
+        .collect()
+    }
+
+    pub fn lut(&self) -> PaletteLut {
+        PaletteLut {
+            inside: self.inside.with_alpha(1.).to_u8_array(),
+            table: (0 .. LUT_SIZE).map(|i| self.gradient(i as f32 / LUT_SIZE as f32).with_alpha(1.).to_u8_array()).collect(),
+            length: self.length as f32,
+            offset: self.offset
+        }
+    }
+
+    fn sort(&mut self) {
+        self.stops.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+    }
+
+    // the invariants the colouring relies on, for palettes read from text or a session
+    pub fn normalized(mut self) -> Result<Self, String> {
+        if self.stops.is_empty() {
+            return Err("no stops".into());
+        }
+        self.length = self.length.max(1);
+        for stop in &mut self.stops {
+            stop.pos = if stop.pos.is_finite() {stop.pos.rem_euclid(1.)} else {0.};
+        }
+        if !self.offset.is_finite() {
+            self.offset = 0.;
+        }
+        if !self.speed.is_finite() {
+            self.speed = 0.;
+        }
+        self.sort();
+        Ok(self)
+    }
+
+    // ---
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = read_text(path)?;
+        let name = Path::new(path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        match Path::new(path).extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+            Some("map") => Self::from_map(&name, &text),
+            Some("ugr") | Some("gradient") => Self::from_ugr(&name, &text),
+            _ => Self::from_text(&text)
+        }
+    }
+
+    fn save(&self, path: &str) -> Result<(), String> {
+        write_text(path, &self.to_text())
+    }
+
+    // one setting per line, "stop <position> <hex colour>" for every stop
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "name {}\nlength {}\noffset {}\nspeed {}\ninside {}\n",
+            self.name, self.length, self.offset, self.speed, self.inside.to_hex()
+        );
+        for s in &self.stops {
+            text += &format!("stop {} {}\n", s.pos, s.color.to_hex());
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut p = Self::new("", 64, &[]);
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let err = |e: String| format!("line {}: {}", n + 1, e);
+            match key {
+                "name" => p.name = rest.trim().to_string(),
+                "length" => p.length = rest.trim().parse::<usize>().map_err(|e| err(e.to_string()))?,
+                "offset" => p.offset = rest.trim().parse().map_err(|e: std::num::ParseFloatError| err(e.to_string()))?,
+                "speed" => p.speed = rest.trim().parse().map_err(|e: std::num::ParseFloatError| err(e.to_string()))?,
+                "inside" => p.inside = Srgba::hex(rest.trim()).map_err(|e| err(e.to_string()))?,
+                "stop" => {
+                    let (pos, color) = rest.trim().split_once(' ').ok_or_else(|| err("stop needs a position and a colour".into()))?;
+                    p.stops.push(Stop {
+                        pos: pos.parse::<f32>().map_err(|e| err(e.to_string()))?,
+                        color: Srgba::hex(color.trim()).map_err(|e| err(e.to_string()))?
+                    });
+                },
+                _ => return Err(err(format!("unknown setting {}", key)))
+            }
+        }
+        p.normalized()
+    }
+
+    // fractint map, one "r g b" line per colour and one colour per iteration
+    pub fn from_map(name: &str, text: &str) -> Result<Self, String> {
+        let colors: Vec<Srgba> = text.lines()
+        .filter_map(|line| {
+            let rgb: Vec<u8> = line.split_whitespace().take(3).map_while(|v| v.parse().ok()).collect();
+            (rgb.len() == 3).then(|| Srgba::rgb_u8(rgb[0], rgb[1], rgb[2]))
+        })
+        .collect();
+        if colors.is_empty() {
+            return Err("no colours in map file".into());
+        }
+        let n = colors.len();
+        let stops: Vec<(f32, Srgba)> = colors.into_iter().enumerate().map(|(i, c)| (i as f32 / n as f32, c)).collect();
+        Ok(Self::new(name, n, &stops))
+    }
+
+    // ultra fractal gradient, the first one in the file, "index=<0..399> color=<bgr as integer>"
+    pub fn from_ugr(name: &str, text: &str) -> Result<Self, String> {
+        let mut p = Self::new(name, 64, &[]);
+        let mut index = None;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with("opacity:") || (line == "}" && !p.stops.is_empty()) {
+                break;
+            }
+            for token in line.split_whitespace() {
+                if let Some(title) = token.strip_prefix("title=") {
+                    p.name = title.trim_matches('"').to_string();
+                } else if let Some(i) = token.strip_prefix("index=") {
+                    index = i.parse::<f32>().ok();
+                } else if let (Some(c), Some(i)) = (token.strip_prefix("color="), index.take()) {
+                    let bgr = c.parse::<u32>().map_err(|e| e.to_string())?;
+                    p.stops.push(Stop {
+                        pos: (i / UGR_SPAN).rem_euclid(1.),
+                        color: Srgba::rgb_u8(bgr as u8, (bgr >> 8) as u8, (bgr >> 16) as u8)
+                    });
+                }
+            }
+        }
+        if p.stops.is_empty() {
+            return Err("no gradient in file".into());
+        }
+        p.normalized()
+    }
+}
+
+// ---
+
+fn startup(
+    mut cmd: Commands,
+    mut images: ResMut<Assets<Image>>
+) {
+    let image_h = images.add(Image::new_fill(
+        Extent3d {width: PREVIEW_WIDTH, height: 1, depth_or_array_layers: 1},
+        TextureDimension::D2,
+        &Srgba::BLACK.to_u8_array(),
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::all()
+    ));
+    cmd.insert_resource(PalettePreview(image_h));
+    cmd.spawn((
+        PalettePanel,
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.),
+            top: Val::Px(10.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0., 0., 0., 0.7)),
+        ZIndex(20),
+        Visibility::Hidden
+    ));
+}
+
+// ---
+
+fn palette_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    panel_q: Single<&mut Visibility, With<PalettePanel>>,
+    mut cycle: ResMut<PaletteCycle>,
+    mut palette: ResMut<Palette>
+) {
+    if keys.just_pressed(KeyCode::KeyC) {
+        let mut vis = panel_q.into_inner();
+        *vis = if *vis == Visibility::Visible {Visibility::Hidden} else {Visibility::Visible};
+    }
+    if !keys.just_pressed(KeyCode::KeyV) {
+        return;
+    }
+    if keys.pressed(KeyCode::ShiftLeft) {
+        palette.speed = -palette.speed;
+    } else if keys.pressed(KeyCode::ControlLeft) {
+        cycle.pulse = !cycle.pulse;
+    } else {
+        cycle.running = !cycle.running;
+    }
+}
+
+// ---
+
+// the pulse bands and the boundary glow are hdr colours above 1, they only glow with bloom on
+fn update_bloom(
+    cycle: Res<PaletteCycle>,
+    shading: Res<DistanceShading>,
+    cam_q: Single<(Entity, Has<Bloom>), With<Cam>>,
+    mut cmd: Commands
+) {
+    let (cam, has_bloom) = cam_q.into_inner();
+    let wanted = cycle.pulse || shading.enabled;
+    if wanted && !has_bloom {
+        cmd.entity(cam).insert(Bloom::NATURAL);
+    } else if !wanted && has_bloom {
+        cmd.entity(cam).remove::<Bloom>();
+    }
+}
+
+// ---
+
+fn cycle(
+    mut cycle: ResMut<PaletteCycle>,
+    palette: Res<Palette>,
+    time: Res<Time>
+) {
+    let dt = time.delta_secs();
+    if cycle.running {
+        cycle.shift = (cycle.shift + palette.speed * dt).rem_euclid(1.);
+    }
+    if cycle.pulse {
+        cycle.phase = (cycle.phase + PULSE_SPEED * dt).rem_euclid(1.);
+    }
+}
+
+// ---
+
+fn import_dropped(
+    mut er: EventReader<FileDragAndDrop>,
+    mut palette: ResMut<Palette>,
+    mut edit: ResMut<PaletteEdit>
+) {
+    for e in er.read() {
+        let FileDragAndDrop::DroppedFile {path_buf, ..} = e else {
+            continue;
+        };
+        let path = path_buf.to_string_lossy();
+        match Palette::load(&path) {
+            Ok(p) => {
+                info!("palette {} loaded", p.name);
+                *palette = p;
+                edit.stop = 0;
+            },
+            Err(e) => warn!("palette {} not loaded: {}", path, e)
+        }
+    }
+}
+
+// ---
+
+fn rebuild_panel(
+    mut cmd: Commands,
+    panel_q: Single<Entity, With<PalettePanel>>,
+    palette: Res<Palette>,
+    mut edit: ResMut<PaletteEdit>,
+    preview: Res<PalettePreview>,
+    mut images: ResMut<Assets<Image>>
+) {
+    if let Some(image) = images.get_mut(&preview.0) {
+        image.data = (0 .. PREVIEW_WIDTH)
+        .flat_map(|i| palette.gradient(i as f32 / PREVIEW_WIDTH as f32).with_alpha(1.).to_u8_array())
+        .collect();
+    }
+    // a palette with fewer stops moves the selection, that is not an edit
+    let edit = edit.bypass_change_detection();
+    edit.stop = edit.stop.min(palette.stops.len().saturating_sub(1));
+    let Some(stop) = palette.stops.get(edit.stop) else {
+        return;
+    };
+
+    let panel = panel_q.into_inner();
+    cmd.entity(panel).despawn_descendants();
+    cmd.entity(panel).with_children(|parent| {
+        let row = |parent: &mut ChildBuilder, label: String, buttons: &[(&str, PaletteButton)]| {
+            parent.spawn(Node {
+                column_gap: Val::Px(6.),
+                align_items: AlignItems::Center,
+                ..default()
+            })
+            .with_children(|row| {
+                row.spawn((
+                    Node {width: Val::Px(150.), ..default()},
+                    Text::new(label),
+                    TextFont {font_size: 14., ..default()}
+                ));
+                for (label, button) in buttons {
+                    row.spawn((
+                        *button,
+                        Node {
+                            padding: UiRect::axes(Val::Px(6.), Val::Px(2.)),
+                            border: UiRect::all(Val::Px(1.)),
+                            ..default()
+                        },
+                        BorderColor(Color::WHITE),
+                        Text::new(*label),
+                        TextFont {font_size: 14., ..default()}
+                    ))
+                    .observe(on_button);
+                }
+            });
+        };
+
+        parent.spawn((
+            Text::new("Palette (C hide, drop .map / .ugr / .txt files to import)"),
+            TextFont {font_size: 14., ..default()}
+        ));
+        row(parent, palette.name.clone(), &[("<", PaletteButton::Preset(-1)), (">", PaletteButton::Preset(1))]);
+
+        // the gradient with a mark on the selected stop
+        parent.spawn((
+            Node {
+                width: Val::Px(PREVIEW_WIDTH as f32),
+                height: Val::Px(16.),
+                border: UiRect::all(Val::Px(1.)),
+                ..default()
+            },
+            BorderColor(Color::WHITE),
+            ImageNode::new(preview.0.clone())
+        ))
+        .with_children(|bar| {
+            bar.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(stop.pos * 100.),
+                    width: Val::Px(2.),
+                    height: Val::Percent(100.),
+                    ..default()
+                },
+                BackgroundColor(Color::WHITE)
+            ));
+        });
+
+        let hsla = Hsla::from(stop.color);
+        row(parent, format!("stop {} / {} {}", edit.stop + 1, palette.stops.len(), stop.color.to_hex()), &[
+            ("<", PaletteButton::Select(-1)), (">", PaletteButton::Select(1)),
+            ("add", PaletteButton::AddStop), ("x", PaletteButton::DeleteStop)
+        ]);
+        row(parent, format!("position {:.3}", stop.pos), &[("-", PaletteButton::Move(-0.01)), ("+", PaletteButton::Move(0.01))]);
+        row(parent, format!("hue {:.0}", hsla.hue), &[("-", PaletteButton::Hue(-10.)), ("+", PaletteButton::Hue(10.))]);
+        row(parent, format!("saturation {:.2}", hsla.saturation), &[("-", PaletteButton::Saturation(-0.05)), ("+", PaletteButton::Saturation(0.05))]);
+        row(parent, format!("lightness {:.2}", hsla.lightness), &[("-", PaletteButton::Lightness(-0.05)), ("+", PaletteButton::Lightness(0.05))]);
+        row(parent, format!("length {}", palette.length), &[("-", PaletteButton::Length(-1)), ("+", PaletteButton::Length(1))]);
+        row(parent, format!("offset {:.2}", palette.offset), &[("-", PaletteButton::Offset(-0.05)), ("+", PaletteButton::Offset(0.05))]);
+        row(parent, format!("cycle speed {:.2}", palette.speed), &[("-", PaletteButton::Speed(-0.05)), ("+", PaletteButton::Speed(0.05))]);
+        row(parent, PALETTE_FILE.to_string(), &[("save", PaletteButton::Save), ("load", PaletteButton::Load)]);
+    });
+}
+
+// ---
+
+fn on_button(
+    click: Trigger<Pointer<Click>>,
+    button_q: Query<&PaletteButton>,
+    mut palette: ResMut<Palette>,
+    mut edit: ResMut<PaletteEdit>
+) {
+    let Ok(button) = button_q.get(click.entity()) else {
+        return;
+    };
+    let n = palette.stops.len();
+    let i = edit.stop.min(n - 1);
+    let step = |i: usize, d: i32, n: usize| (i as i32 + d).rem_euclid(n as i32) as usize;
+    let recolor = |p: &mut Palette, f: &dyn Fn(Hsla) -> Hsla| {
+        p.stops[i].color = Srgba::from(f(Hsla::from(p.stops[i].color)));
+    };
+    match *button {
+        PaletteButton::Preset(d) => {
+            let presets = Palette::presets();
+            edit.preset = step(edit.preset, d, presets.len());
+            *palette = presets[edit.preset].clone();
+            edit.stop = 0;
+        },
+        PaletteButton::Select(d) => edit.stop = step(i, d, n),
+        PaletteButton::AddStop => {
+            // halfway to the next stop, in the colour the gradient already has there
+            let next = palette.stops[(i + 1) % n].pos + if i + 1 == n {1.} else {0.};
+            let pos = ((palette.stops[i].pos + next) / 2.).rem_euclid(1.);
+            let color = palette.gradient(pos);
+            palette.stops.push(Stop {pos, color});
+            palette.sort();
+            edit.stop = palette.stops.iter().position(|s| s.pos == pos).unwrap_or(0);
+        },
+        PaletteButton::DeleteStop => {
+            if n > 1 {
+                palette.stops.remove(i);
+                edit.stop = i.min(n - 2);
+            }
+        },
+        PaletteButton::Move(d) => {
+            let moved = Stop {pos: (palette.stops[i].pos + d).clamp(0., 0.999), ..palette.stops[i]};
+            palette.stops[i] = moved;
+            palette.sort();
+            edit.stop = palette.stops.iter().position(|s| *s == moved).unwrap_or(0);
+        },
+        PaletteButton::Hue(d) => recolor(&mut palette, &|c| c.with_hue((c.hue + d).rem_euclid(360.))),
+        PaletteButton::Saturation(d) => recolor(&mut palette, &|c| c.with_saturation((c.saturation + d).clamp(0., 1.))),
+        PaletteButton::Lightness(d) => recolor(&mut palette, &|c| c.with_lightness((c.lightness + d).clamp(0., 1.))),
+        PaletteButton::Length(d) => palette.length = (palette.length as i32 + d).max(1) as usize,
+        PaletteButton::Offset(d) => palette.offset = (palette.offset + d).rem_euclid(1.),
+        PaletteButton::Speed(d) => palette.speed += d,
+        PaletteButton::Save => match palette.save(PALETTE_FILE) {
+            Ok(_) => info!("palette saved to {}", PALETTE_FILE),
+            Err(e) => warn!("palette not saved: {}", e)
+        },
+        PaletteButton::Load => match Palette::load(PALETTE_FILE) {
+            Ok(p) => {
+                *palette = p;
+                edit.stop = 0;
+            },
+            Err(e) => warn!("palette not loaded: {}", e)
+        }
+    }
+}
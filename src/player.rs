@@ -12,8 +12,9 @@ use bevy::{
      }
 };
 use crate::{
-    animator::{AllAnimations, AnimationKey, CurrentAnimation}, camera::CamReset,
-    shared::{cell2xz, xz2cell, Focus, PLAYER_START_CELL, CoLayer},
+    animator::{AllAnimations, AnimationKey, CurrentAnimation}, camera::{Cam, CamReset},
+    fractal::{rebase_origin, FractallBounds},
+    shared::{cell2xz, xz2cell, Focus, CELL_SIZE, PLAYER_START_CELL, VALLEY_SIZE, CoLayer},
     GameState,
 
 };
@@ -25,7 +26,8 @@ impl Plugin for PlayerPlugin {
         app
         .init_resource::<PlayerCell>()
         .add_systems(Startup, startup)
-        .add_systems(Update, change_cell.never_param_warn())
+        .add_event::<WorldShift>()
+        .add_systems(Update, (change_cell.never_param_warn(), recentre).chain().before(rebase_origin))
         .add_systems(OnEnter(GameState::Game), enter_game.never_param_warn())
         .add_systems(Update, (
             keyboard_input,
@@ -54,7 +56,7 @@ pub struct Player;
 pub struct PlayerChild;
 
 #[derive(Resource, Debug)]
-pub struct PlayerCell(pub usize, pub usize);
+pub struct PlayerCell(pub i32, pub i32);
 
 impl FromWorld for PlayerCell {
     fn from_world(_world: &mut World) -> Self {
@@ -80,6 +82,15 @@ pub struct Running;
 #[derive(Event)]
 pub struct AdjustY(pub f32);
 
+// cells the bounds moved by when the player went too far from the centre of the valley
+#[derive(Event)]
+pub struct WorldShift(pub IVec2);
+
+// how far from the centre of the valley the player gets before the world moves along
+const RECENTRE_CELLS: i32 = 256;
+// shifts are whole multiples of this, every lod block and valley chunk then keeps its grid and can be moved as it is
+pub const SHIFT_CELLS: i32 = 256;
+
 // ---
 
 fn startup(
@@ -228,6 +239,34 @@ fn change_cell(
 
 // ---
 
+// away from the centre of the valley the bounds move along by whole cells and the player moves back,
+// so the fractal goes on without end and world coordinates stay small enough for f32
+fn recentre(
+    mut cell: ResMut<PlayerCell>,
+    mut bounds: ResMut<FractallBounds>,
+    p_q: Single<&mut Transform, (With<Player>, Without<Cam>)>,
+    cam_q: Single<&mut Transform, (With<Cam>, Without<Player>)>,
+    mut ew: EventWriter<WorldShift>
+) {
+    let half = VALLEY_SIZE as i32 / 2;
+    if (cell.0 - half).abs() <= RECENTRE_CELLS && (cell.1 - half).abs() <= RECENTRE_CELLS {
+        return;
+    }
+    let align = |c: i32| ((c - half) as f32 / SHIFT_CELLS as f32).round() as i32 * SHIFT_CELLS;
+    let d = IVec2::new(align(cell.0), align(cell.1));
+    // whole cells keep every cell on the same complex point, so nothing changes height
+    *bounds = bounds.shifted(d);
+
+    let offset = Vec3::new(d.x as f32 * CELL_SIZE, 0., d.y as f32 * CELL_SIZE);
+    p_q.into_inner().translation -= offset;
+    cam_q.into_inner().translation -= offset;
+    cell.0 -= d.x;
+    cell.1 -= d.y;
+    ew.send(WorldShift(d));
+}
+
+// ---
+
 #[allow(dead_code)]
 fn switch_anim (
     p_q: Single<(Entity, &mut CurrentAnimation), With<Player>>,
@@ -0,0 +1,191 @@
+use avian3d::prelude::LinearVelocity;
+use bevy::{input::keyboard::KeyboardInput, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::CamFollowParams,
+    formula::{Formula, FORMULAS},
+    fractal::{rebase_origin, FractallBounds, JuliaParent},
+    heights::HeightMap,
+    history::RecordView,
+    palette::Palette,
+    player::{Player, PlayerCell},
+    shared::{TilesCount, MAX_ITER_LIMIT, MAX_TILES_COUNT, MIN_ITER, MIN_TILES_COUNT}
+};
+
+pub struct SessionPlugin;
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .insert_resource(PendingSession::from_args())
+        .add_systems(Update, session_keys.run_if(on_event::<KeyboardInput>).before(apply_session))
+        .add_systems(Update, apply_session.before(rebase_origin).run_if(|p: Res<PendingSession>| p.0.is_some()))
+        ;
+    }
+}
+
+// ---
+
+// file name on desktop, local storage key on wasm
+const SESSION_FILE: &str = "session.ron";
+
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    bounds: FractallBounds,
+    julia_parent: Option<FractallBounds>,
+    formula: Formula,
+    player_cell: (i32, i32),
+    player: Transform,
+    camera: CamFollowParams,
+    tiles: usize,
+    // sessions saved before palettes existed keep the current one
+    #[serde(default)]
+    palette: Option<Palette>,
+    #[serde(default)]
+    heights: Option<HeightMap>
+}
+
+// loaded but not applied yet, the player only exists after startup
+#[derive(Resource, Default)]
+pub struct PendingSession(Option<Session>);
+
+impl PendingSession {
+    // fractal-valley --session <file>
+    fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let Some(path) = args.iter().position(|a| a == "--session").and_then(|i| args.get(i + 1)) else {
+            return Self::default();
+        };
+        match load(path) {
+            Ok(s) => Self(Some(s)),
+            Err(e) => {
+                warn!("session {} not loaded: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+}
+
+// ---
+
+fn load(path: &str) -> Result<Session, String> {
+    ron::from_str(&read_text(path)?).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_text(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_text(path: &str, text: &str) -> Result<(), String> {
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, String> {
+    web_sys::window()
+    .and_then(|w| w.local_storage().ok().flatten())
+    .ok_or_else(|| "no local storage".to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn read_text(path: &str) -> Result<String, String> {
+    local_storage()?.get_item(path).ok().flatten().ok_or_else(|| "nothing saved".to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write_text(path: &str, text: &str) -> Result<(), String> {
+    local_storage()?.set_item(path, text).map_err(|_| "local storage is full".to_string())
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn session_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut pending: ResMut<PendingSession>,
+    bounds: Res<FractallBounds>,
+    julia_parent: Res<JuliaParent>,
+    formula: Res<Formula>,
+    cell: Res<PlayerCell>,
+    tiles: Res<TilesCount>,
+    cam: Res<CamFollowParams>,
+    palette: Res<Palette>,
+    heights: Res<HeightMap>,
+    p_q: Single<&Transform, With<Player>>
+) {
+    if keys.just_pressed(KeyCode::F5) {
+        let session = Session {
+            bounds: bounds.clone(),
+            julia_parent: julia_parent.0.clone(),
+            formula: *formula,
+            player_cell: (cell.0, cell.1),
+            player: *p_q.into_inner(),
+            camera: cam.clone(),
+            tiles: tiles.0,
+            palette: Some(palette.clone()),
+            heights: Some(heights.clone())
+        };
+        let res = ron::ser::to_string_pretty(&session, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())
+        .and_then(|text| write_text(SESSION_FILE, &text));
+        match res {
+            Ok(_) => info!("session saved to {}", SESSION_FILE),
+            Err(e) => warn!("session not saved: {}", e)
+        }
+    }
+    if keys.just_pressed(KeyCode::F9) {
+        match load(SESSION_FILE) {
+            Ok(s) => pending.0 = Some(s),
+            Err(e) => warn!("session not loaded: {}", e)
+        }
+    }
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn apply_session(
+    mut pending: ResMut<PendingSession>,
+    mut bounds: ResMut<FractallBounds>,
+    mut julia_parent: ResMut<JuliaParent>,
+    mut formula: ResMut<Formula>,
+    mut cell: ResMut<PlayerCell>,
+    mut tiles: ResMut<TilesCount>,
+    mut cam: ResMut<CamFollowParams>,
+    mut palette: ResMut<Palette>,
+    mut heights: ResMut<HeightMap>,
+    p_q: Single<(&mut Transform, &mut LinearVelocity), With<Player>>,
+    mut rw: EventWriter<RecordView>
+) {
+    let Some(s) = pending.0.take() else {
+        return;
+    };
+    if s.formula.index >= FORMULAS.len() {
+        warn!("session not loaded: unknown formula {}", s.formula.index);
+        return;
+    }
+    if let Some(p) = s.palette {
+        match p.normalized() {
+            Ok(p) => *palette = p,
+            Err(e) => warn!("session palette not loaded: {}", e)
+        }
+    }
+    if let Some(h) = s.heights {
+        *heights = h;
+    }
+    *bounds = s.bounds;
+    julia_parent.0 = s.julia_parent;
+    *formula = s.formula;
+    formula.max_iter = formula.max_iter.clamp(MIN_ITER, MAX_ITER_LIMIT);
+    cell.0 = s.player_cell.0;
+    cell.1 = s.player_cell.1;
+    // odd, so the player stays on the centre tile
+    tiles.0 = s.tiles.clamp(MIN_TILES_COUNT, MAX_TILES_COUNT) | 1;
+    *cam = s.camera;
+    let (mut t, mut lv) = p_q.into_inner();
+    *t = s.player;
+    lv.0 = Vec3::ZERO;
+    rw.send_default();
+}
@@ -0,0 +1,303 @@
+use std::{fs::File, io::{self, BufWriter, Write}, sync::Arc, time::{SystemTime, UNIX_EPOCH}};
+
+use bevy::{
+    input::keyboard::KeyboardInput,
+    prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task}
+};
+
+use crate::{
+    deep::ReferenceOrbit,
+    formula::Formula,
+    fractal::{FractalView, FractallBounds},
+    palette::Palette
+};
+
+pub struct SnapshotPlugin;
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<SnapshotSettings>()
+        .init_resource::<SnapshotRender>()
+        .add_systems(Update, snapshot_keys.run_if(on_event::<KeyboardInput>))
+        .add_systems(Update, receive_snapshot.after(snapshot_keys))
+        ;
+    }
+}
+
+// ---
+
+const ROWS_PER_JOB: u32 = 32;
+// image widths offered by the resolution key, the height follows the bounds
+const SNAPSHOT_WIDTHS: [u32; 4] = [1920, 3840, 7680, 15360];
+const SNAPSHOT_SAMPLES: [u32; 4] = [1, 2, 3, 4];
+
+// supersample n takes n x n samples per pixel, exr adds the raw iteration counts as float
+#[derive(Resource, Debug)]
+pub struct SnapshotSettings {
+    pub width: u32,
+    pub supersample: u32,
+    pub exr: bool
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        Self {width: 7680, supersample: 2, exr: false}
+    }
+}
+
+struct SnapshotChunk {
+    rows: (u32, u32),
+    pixels: Vec<u8>,
+    raw: Vec<f32>
+}
+
+// dropping the tasks cancels the snapshot in flight
+#[derive(Resource, Default)]
+pub struct SnapshotRender {
+    tasks: Vec<Task<SnapshotChunk>>,
+    chunks: Vec<SnapshotChunk>,
+    size: (u32, u32),
+    exr: bool,
+    meta: Vec<(String, String)>
+}
+
+// ---
+
+fn snapshot_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<SnapshotSettings>,
+    mut render: ResMut<SnapshotRender>,
+    bounds: Res<FractallBounds>,
+    formula: Res<Formula>,
+    orbit: Res<ReferenceOrbit>,
+    palette: Res<Palette>
+) {
+    if !keys.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+    let next = |list: &[u32], v: u32| list[(list.iter().position(|x| *x == v).unwrap_or(0) + 1) % list.len()];
+    if keys.pressed(KeyCode::ShiftLeft) {
+        settings.width = next(&SNAPSHOT_WIDTHS, settings.width);
+        info!("snapshot width: {}", settings.width);
+    } else if keys.pressed(KeyCode::ControlLeft) {
+        settings.supersample = next(&SNAPSHOT_SAMPLES, settings.supersample);
+        info!("snapshot supersample: {}x{}", settings.supersample, settings.supersample);
+    } else if keys.pressed(KeyCode::AltLeft) {
+        settings.exr = !settings.exr;
+        info!("snapshot exr: {}", settings.exr);
+    } else {
+        start_snapshot(&settings, &mut render, &bounds, &formula, &orbit, &palette);
+    }
+}
+
+// ---
+
+fn start_snapshot(
+    settings: &SnapshotSettings,
+    render: &mut SnapshotRender,
+    bounds: &FractallBounds,
+    formula: &Formula,
+    orbit: &ReferenceOrbit,
+    palette: &Palette
+) {
+    // the browser has no file system to write into
+    if cfg!(target_arch = "wasm32") {
+        warn!("snapshots are not available in the browser");
+        return;
+    }
+    let b_width = bounds.x.1 - bounds.x.0;
+    let b_height = bounds.y.1 - bounds.y.0;
+    let width = settings.width;
+    let height = ((b_height / b_width) * width as f64).round().max(1.) as u32;
+    let step = (b_width / width as f64, b_height / height as f64);
+    let start = (bounds.x.0, bounds.y.0);
+    let ss = settings.supersample;
+    let exr = settings.exr;
+    let palette = Arc::new(palette.clone());
+    let view = FractalView::new(formula, bounds, orbit);
+    let pool = AsyncComputeTaskPool::get();
+
+    let origin = bounds.origin_f64();
+    let name = match formula.julia {
+        Some(c) => format!("{} Julia c = {} {:+}i", formula.get().name(), c.0, c.1),
+        None => formula.get().name().to_string()
+    };
+    render.meta = vec![
+        ("Software".into(), "fractal-valley".into()),
+        ("Formula".into(), name),
+        ("Origin".into(), format!("{} {}", bounds.origin.0, bounds.origin.1)),
+        ("Bounds".into(), format!("x {} {} y {} {}", bounds.x.0, bounds.x.1, bounds.y.0, bounds.y.1)),
+        ("Center".into(), format!("{} {}", origin.0 + (bounds.x.0 + bounds.x.1) / 2., origin.1 + (bounds.y.0 + bounds.y.1) / 2.)),
+        ("Width".into(), format!("{:e}", b_width)),
+        ("MaxIter".into(), formula.max_iter.to_string()),
+        ("Smooth".into(), formula.smooth.to_string()),
+        ("Palette".into(), palette.to_text()),
+    ];
+    render.size = (width, height);
+    render.exr = exr;
+    render.chunks.clear();
+    render.tasks.clear();
+    for row in (0 .. height).step_by(ROWS_PER_JOB as usize) {
+        let rows = (row, (row + ROWS_PER_JOB).min(height));
+        let view = view.clone();
+        let palette = palette.clone();
+        render.tasks.push(pool.spawn(async move {
+            snapshot_chunk(&view, &palette, start, step, width, rows, ss, exr)
+        }));
+    }
+    info!("snapshot {}x{} started", width, height);
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments)]
+fn snapshot_chunk(
+    view: &FractalView,
+    palette: &Palette,
+    start: (f64, f64),
+    step: (f64, f64),
+    width: u32,
+    rows: (u32, u32),
+    ss: u32,
+    exr: bool
+) -> SnapshotChunk {
+    let mut pixels = Vec::with_capacity(((rows.1 - rows.0) * width * 3) as usize);
+    let mut raw = Vec::new();
+    for j in rows.0 .. rows.1 {
+        for i in 0 .. width {
+            // samples are averaged in linear space
+            let mut sum = LinearRgba::NONE;
+            // the raw value is the sample at the pixel centre, or next to it for an even grid
+            let mut centre = 0.;
+            for sj in 0 .. ss {
+                for si in 0 .. ss {
+                    let x = start.0 + (i as f64 + (si as f64 + 0.5) / ss as f64) * step.0;
+                    let y = start.1 + (j as f64 + (sj as f64 + 0.5) / ss as f64) * step.1;
+                    let value = view.calc_color(x, y);
+                    if si == ss / 2 && sj == ss / 2 {
+                        centre = value;
+                    }
+                    sum += LinearRgba::from(palette.color(value));
+                }
+            }
+            let color = Color::from(sum / (ss * ss) as f32).to_srgba().to_u8_array();
+            pixels.extend_from_slice(&color[..3]);
+            if exr {
+                raw.push(centre);
+            }
+        }
+    }
+    SnapshotChunk{rows, pixels, raw}
+}
+
+// ---
+
+fn receive_snapshot(
+    mut render: ResMut<SnapshotRender>
+) {
+    if render.tasks.is_empty() {
+        return;
+    }
+    let mut done = Vec::new();
+    render.tasks.retain_mut(|task| {
+        match block_on(future::poll_once(task)) {
+            Some(chunk) => {
+                done.push(chunk);
+                false
+            },
+            None => true
+        }
+    });
+    render.chunks.extend(done);
+    if !render.tasks.is_empty() {
+        return;
+    }
+
+    let mut chunks = std::mem::take(&mut render.chunks);
+    chunks.sort_by_key(|c| c.rows.0);
+    let (width, height) = render.size;
+    let meta = std::mem::take(&mut render.meta);
+    let exr = render.exr;
+    // encoding an 8K image takes a while, keep it off the main thread
+    AsyncComputeTaskPool::get().spawn(async move {
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let name = format!("fractal-{}", stamp);
+        let pixels: Vec<u8> = chunks.iter().flat_map(|c| c.pixels.iter().copied()).collect();
+        match write_png(&format!("{}.png", name), width, height, &pixels, &meta) {
+            Ok(_) => info!("saved {}.png", name),
+            Err(e) => warn!("snapshot failed: {}", e)
+        }
+        if exr {
+            let raw: Vec<f32> = chunks.iter().flat_map(|c| c.raw.iter().copied()).collect();
+            match write_exr(&format!("{}.exr", name), width, height, &raw) {
+                Ok(_) => info!("saved {}.exr", name),
+                Err(e) => warn!("snapshot failed: {}", e)
+            }
+        }
+    }).detach();
+}
+
+// ---
+
+fn write_png(path: &str, width: u32, height: u32, pixels: &[u8], meta: &[(String, String)]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (key, text) in meta {
+        encoder.add_itxt_chunk(key.clone(), text.clone())?;
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// ---
+
+// single channel uncompressed scanline exr, one line per block
+fn write_exr(path: &str, width: u32, height: u32, values: &[f32]) -> io::Result<()> {
+    fn attr(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        out.extend(name.as_bytes());
+        out.push(0);
+        out.extend(kind.as_bytes());
+        out.push(0);
+        out.extend((value.len() as i32).to_le_bytes());
+        out.extend(value);
+    }
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+    // channel Y, FLOAT, not linear, sampling 1 x 1
+    let mut channels = b"Y\0".to_vec();
+    channels.extend(2i32.to_le_bytes());
+    channels.extend([0, 0, 0, 0]);
+    channels.extend(1i32.to_le_bytes());
+    channels.extend(1i32.to_le_bytes());
+    channels.push(0);
+
+    let mut out = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    attr(&mut out, "channels", "chlist", &channels);
+    attr(&mut out, "compression", "compression", &[0]);
+    attr(&mut out, "dataWindow", "box2i", &window);
+    attr(&mut out, "displayWindow", "box2i", &window);
+    attr(&mut out, "lineOrder", "lineOrder", &[0]);
+    attr(&mut out, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attr(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+    attr(&mut out, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    out.push(0);
+
+    let line = width as u64 * 4;
+    let table_end = out.len() as u64 + height as u64 * 8;
+    for y in 0 .. height as u64 {
+        out.extend((table_end + y * (line + 8)).to_le_bytes());
+    }
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&out)?;
+    for (y, row) in values.chunks(width as usize).enumerate() {
+        file.write_all(&(y as i32).to_le_bytes())?;
+        file.write_all(&(line as i32).to_le_bytes())?;
+        for v in row {
+            file.write_all(&v.to_le_bytes())?;
+        }
+    }
+    file.flush()
+}
@@ -7,8 +7,12 @@ use crate::camera::{
     Cam, 
     CamFollowParams
 };
+use crate::formula::Formula;
+use crate::fractal::FractallCollors;
+use crate::heights::HeightMap;
+use crate::inspector::{InspectorSettings, OrbitTarget};
 use crate::player::Player;
-use crate::shared::CELL_HEIGHT;
+use crate::shared::{cell2xz, xz2cell, CoLayer};
 
 pub struct TargetSelectPlugin;
 impl Plugin for TargetSelectPlugin {
@@ -19,6 +23,7 @@ impl Plugin for TargetSelectPlugin {
 
 // ---
 
+#[allow(clippy::too_many_arguments)]
 fn mouse_click(
     q_camera: Single<(&Camera, &GlobalTransform), With<Cam>>,
     q_window: Single<&Window, With<PrimaryWindow>>,
@@ -26,12 +31,19 @@ fn mouse_click(
     keys: Res<ButtonInput<KeyCode>>,
     raycast_q: SpatialQuery,
     p_q: Single<&mut Transform, With<Player>>,
-    m_q: Query<&Transform, Without<Player>>,
-    mut cp: ResMut<CamFollowParams>
+    colors: Res<FractallCollors>,
+    heights: Res<HeightMap>,
+    formula: Res<Formula>,
+    mut cp: ResMut<CamFollowParams>,
+    mut orbit_target: ResMut<OrbitTarget>,
+    inspector: Res<InspectorSettings>
 ) {
     let (camera, camera_gtransform) = q_camera.into_inner();
 
-    if buttons.just_pressed(MouseButton::Left) && keys.pressed(KeyCode::ShiftLeft){
+    let shift = keys.pressed(KeyCode::ShiftLeft);
+    // with orbits shown, LCtrl picks the cell whose orbit is drawn
+    let ctrl = keys.pressed(KeyCode::ControlLeft) && inspector.orbit;
+    if buttons.just_pressed(MouseButton::Left) && (shift || ctrl) {
         let window = q_window.into_inner();
         let Some(cursor_position) = window.cursor_position() else {
             return;
@@ -43,14 +55,23 @@ fn mouse_click(
 
         if let Some(hit) = raycast_q.cast_ray(
             ray.origin, 
-            ray.direction.into(),
+            ray.direction,
             f32::MAX,
             true, 
-            &SpatialQueryFilter::default()
+            &SpatialQueryFilter::from_mask(CoLayer::Tile)
         ) {
-            if let Ok(m_t)  = m_q.get(hit.entity) {
-                let mut t = p_q.into_inner();
-                t.translation = m_t.translation.with_y(m_t.translation.y + CELL_HEIGHT * m_t.scale.y * 0.5 + 1.);
+            // the valley is one collider per chunk, so find the cell from the hit point
+            let point = ray.origin + *ray.direction * hit.distance - hit.normal * 0.01;
+            let cell = xz2cell(point);
+            if !colors.range().is_some_and(|r| (r.0.0 .. r.0.1).contains(&cell.0) && (r.1.0 .. r.1.1).contains(&cell.1)) {
+                return;
+            }
+            let mut t = p_q.into_inner();
+            if ctrl {
+                // picking the player cell follows the player again
+                orbit_target.0 = Some(cell).filter(|c| *c != xz2cell(t.translation));
+            } else {
+                t.translation = cell2xz(cell).with_y(heights.cell_top(&colors, cell, formula.max_iter) + 1.);
                 cp.tranlation_bias = cp.tranlation_bias.normalize() * 8.;
             }
         }
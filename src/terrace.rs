@@ -0,0 +1,160 @@
+use avian3d::prelude::Collider;
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues}
+};
+
+use crate::shared::{cell2xz, CELL_HEIGHT, CELL_SIZE};
+
+// ---
+
+// one terrace slab in world space
+pub struct Block {
+    pub min: Vec2,
+    pub size: Vec2,
+    pub bottom: f32,
+    pub top: f32,
+    pub color: [f32; 4],
+    pub value: f32,
+    // distance estimate in cells, 0 when not computed
+    pub distance: f32
+}
+
+// slab covering cells x cells from the cell, top comes from the height mapping of the iteration value
+pub fn cell_block(cell: (i32, i32), cells: i32, value: f32, distance: f32, top: f32, color: Color) -> Block {
+    let min = cell2xz(cell) - Vec3::splat(CELL_SIZE / 2.);
+    Block {
+        min: Vec2::new(min.x, min.z),
+        size: Vec2::splat(cells as f32 * CELL_SIZE),
+        bottom: top - CELL_HEIGHT,
+        top,
+        color: LinearRgba::from(color).to_f32_array(),
+        value,
+        distance
+    }
+}
+
+// ---
+
+#[derive(Default)]
+pub struct TerraceMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub colors: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+    pub blocks: TerraceBlocks
+}
+
+// first vertex, iteration value, distance and top of every block, enough to recolour or raise a mesh without rebuilding it
+#[derive(Component, Default)]
+pub struct TerraceBlocks(pub Vec<(u32, f32, f32, f32)>);
+
+impl TerraceBlocks {
+    pub fn recolor(&self, mesh: &mut Mesh, color: impl Fn(f32, f32) -> [f32; 4]) {
+        let Some(VertexAttributeValues::Float32x4(colors)) = mesh.attribute_mut(Mesh::ATTRIBUTE_COLOR) else {
+            return;
+        };
+        let len = colors.len() as u32;
+        for (k, (start, value, distance, _)) in self.0.iter().enumerate() {
+            let end = self.0.get(k + 1).map_or(len, |b| b.0);
+            colors[*start as usize .. end as usize].fill(color(*value, *distance));
+        }
+    }
+
+    // offsets that put the blocks of a rebuilt mesh where the old ones stand, None when the blocks don't line up
+    pub fn morph_from(&self, old: &TerraceBlocks, old_morph: Option<&TerraceMorph>) -> Option<TerraceMorph> {
+        if old.0.len() != self.0.len() {
+            return None;
+        }
+        let delta = self.0.iter().zip(&old.0).enumerate().map(|(k, (new, old))| {
+            let lift = old_morph.map_or(0., |m| m.delta[k] * m.applied);
+            old.3 + lift - new.3
+        }).collect();
+        Some(TerraceMorph{delta, applied: 0.})
+    }
+
+    // moves every block by its offset times factor, from where the last call left it
+    pub fn raise(&self, mesh: &mut Mesh, morph: &mut TerraceMorph, factor: f32) {
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) else {
+            return;
+        };
+        let len = positions.len() as u32;
+        let step = factor - morph.applied;
+        for (k, (start, ..)) in self.0.iter().enumerate() {
+            let end = self.0.get(k + 1).map_or(len, |b| b.0);
+            for p in &mut positions[*start as usize .. end as usize] {
+                p[1] += morph.delta[k] * step;
+            }
+        }
+        morph.applied = factor;
+    }
+}
+
+// a mesh on its way from the old block heights to the new ones, applied is the share of the offsets in the vertices
+#[derive(Component, Debug)]
+pub struct TerraceMorph {
+    pub delta: Vec<f32>,
+    pub applied: f32
+}
+
+impl TerraceMesh {
+    // corners go counter clockwise as seen from outside
+    fn quad(&mut self, corners: [Vec3; 4], normal: Vec3, color: [f32; 4]) {
+        let base = self.positions.len() as u32;
+        for c in corners {
+            self.positions.push(c.to_array());
+            self.normals.push(normal.to_array());
+            self.colors.push(color);
+        }
+        self.indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    pub fn add_block(&mut self, b: &Block, bottom_face: bool) {
+        let (x0, z0) = (b.min.x, b.min.y);
+        let (x1, z1) = (b.min.x + b.size.x, b.min.y + b.size.y);
+        let (y0, y1) = (b.bottom, b.top);
+        self.blocks.0.push((self.positions.len() as u32, b.value, b.distance, b.top));
+        self.quad(
+            [Vec3::new(x0, y1, z0), Vec3::new(x0, y1, z1), Vec3::new(x1, y1, z1), Vec3::new(x1, y1, z0)],
+            Vec3::Y, b.color
+        );
+        self.quad(
+            [Vec3::new(x0, y0, z0), Vec3::new(x0, y0, z1), Vec3::new(x0, y1, z1), Vec3::new(x0, y1, z0)],
+            Vec3::NEG_X, b.color
+        );
+        self.quad(
+            [Vec3::new(x1, y0, z0), Vec3::new(x1, y1, z0), Vec3::new(x1, y1, z1), Vec3::new(x1, y0, z1)],
+            Vec3::X, b.color
+        );
+        self.quad(
+            [Vec3::new(x0, y0, z0), Vec3::new(x0, y1, z0), Vec3::new(x1, y1, z0), Vec3::new(x1, y0, z0)],
+            Vec3::NEG_Z, b.color
+        );
+        self.quad(
+            [Vec3::new(x0, y0, z1), Vec3::new(x1, y0, z1), Vec3::new(x1, y1, z1), Vec3::new(x0, y1, z1)],
+            Vec3::Z, b.color
+        );
+        if bottom_face {
+            self.quad(
+                [Vec3::new(x0, y0, z0), Vec3::new(x1, y0, z0), Vec3::new(x1, y0, z1), Vec3::new(x0, y0, z1)],
+                Vec3::NEG_Y, b.color
+            );
+        }
+    }
+
+    pub fn collider(&self) -> Collider {
+        Collider::trimesh(
+            self.positions.iter().map(|p| Vec3::from_array(*p)).collect(),
+            self.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect()
+        )
+    }
+
+    pub fn into_mesh(self) -> Mesh {
+        Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, self.colors)
+        .with_inserted_indices(Indices::U32(self.indices))
+    }
+}
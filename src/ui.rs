@@ -1,12 +1,17 @@
 use bevy::prelude::*;
 
-use crate::player::PlayerCell;
+use crate::{dive::DiveSettings, formula::Formula, fractal::FractallBounds, player::PlayerCell};
 pub struct UIPlugin;
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app
         .add_systems(Startup, startup)
-        .add_systems(Update, display.run_if(resource_changed::<PlayerCell>))
+        .add_systems(Update, display.run_if(
+            resource_changed::<PlayerCell>
+            .or(resource_changed::<Formula>)
+            .or(resource_changed::<FractallBounds>)
+            .or(resource_changed::<DiveSettings>)
+        ))
         ;
     }
 }
@@ -37,10 +42,18 @@ fn startup(
 
 fn display(
     cell: Res<PlayerCell>,
+    formula: Res<Formula>,
+    bounds: Res<FractallBounds>,
+    dive: Res<DiveSettings>,
     ind_q: Single<&mut Text, With<IndCell>>,
 ) {
     let mut ind = ind_q.into_inner();
-    ind.0 = format!("{} / {}", cell.0, cell.1);
+    let name = match formula.julia {
+        Some(c) => format!("{} Julia c = {} {:+}i", formula.get().name(), c.0, c.1),
+        None => formula.get().name().to_string()
+    };
+    let iter = format!("max iter: {}{}", formula.max_iter, if formula.auto_iter {" (auto)"} else {""});
+    ind.0 = format!("{}\n{}\nwidth: {:.3e}, dive x{}\n{} / {}", name, iter, bounds.x.1 - bounds.x.0, dive.factor, cell.0, cell.1);
 }
 
 // ---
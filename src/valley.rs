@@ -1,12 +1,17 @@
 use avian3d::prelude::{Collider, RigidBody, CollisionLayers, LayerMask};
 use bevy::{
-    pbr::{NotShadowCaster, NotShadowReceiver}, prelude::*
+    input::keyboard::KeyboardInput, pbr::{NotShadowCaster, NotShadowReceiver}, prelude::*, render::mesh::VertexAttributeValues, utils::HashMap
 };
 
 use crate::{
-    fractal::FractallCollors, 
-    player::{AdjustY, Player}, 
-    shared::{cell2xz, get_colorset, TilesCenter, CELL_HEIGHT, CELL_SIZE, PLAYER_START_CELL, TILES_COUNT, CoLayer}
+    dive::Dive,
+    formula::Formula,
+    fractal::{do_fractal, DistanceShading, FractallCollors}, 
+    heights::HeightMap,
+    palette::{glow_tile, Palette, PaletteCycle},
+    player::{AdjustY, Player, WorldShift}, 
+    shared::{TilesCenter, TilesCount, CELL_SIZE, MAX_TILES_COUNT, MIN_TILES_COUNT, PLAYER_START_CELL, CoLayer},
+    terrace::{cell_block, TerraceBlocks, TerraceMesh, TerraceMorph}
 };
 
 
@@ -15,7 +20,15 @@ impl Plugin for ValleyPlugin {
     fn build(&self, app: &mut App) {
         app
         .add_systems(Startup, startup)
-        .add_systems(Update, repaint.run_if(resource_changed::<FractallCollors>))
+        .init_resource::<ValleyChunks>()
+        .add_systems(Update, change_tiles_count.run_if(on_event::<KeyboardInput>).before(do_fractal))
+        .add_systems(Update, repaint.after(do_fractal).run_if(
+            resource_changed::<FractallCollors>
+            .or(resource_changed::<Palette>)
+            .or(resource_changed::<PaletteCycle>)
+            .or(resource_changed::<HeightMap>)
+            .or(resource_changed::<DistanceShading>)
+        ))
         // .add_systems(Update, show_gizmos)
         ;
     }
@@ -23,68 +36,33 @@ impl Plugin for ValleyPlugin {
 
 // ---
 
+// chunks are aligned to the valley grid, so they stay put while the window slides
+const CHUNK_CELLS: i32 = 16;
+
+type CellRange = ((i32, i32), (i32, i32));
+
+// the part of the window the chunk currently shows
 #[derive(Component, Debug)]
-pub struct Tile(usize, usize);
+pub struct Chunk(Option<CellRange>);
+
+#[derive(Resource, Default)]
+pub struct ValleyChunks(HashMap<(i32, i32), Entity>);
 
 #[derive(Resource)]
-pub struct MaterialSet(pub Vec<Handle<StandardMaterial>>);
+pub struct ValleyMaterial(Handle<StandardMaterial>);
 
 // ---
 
 fn startup(
     mut cmd: Commands,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>
 ) {
-
-    let color_vec:Vec<Handle<StandardMaterial>> = get_colorset()
-    .into_iter()
-    .map(|c| {
-        materials.add(
-            StandardMaterial {
-                base_color: c,
-                alpha_mode: AlphaMode::Mask(0.1),
-                // alpha_mode: AlphaMode::Multiply,
-                // alpha_mode: AlphaMode::Add,
-                
-                emissive: LinearRgba::from(c),
-                ..default()
-            })
-    })
-    .collect()
-    ;
-
-    cmd.insert_resource(MaterialSet(color_vec));
-
-    // ---
-
-    let tile_mesh = meshes.add(Cuboid::from_size(Vec3::new(CELL_SIZE, CELL_HEIGHT, CELL_SIZE)));
-    // let tile_mesh = meshes.add(Cylinder::new(CELL_SIZE * 0.5, CELL_HEIGHT));
-    let center = cell2xz(PLAYER_START_CELL);
-    let start = - ((TILES_COUNT - 1 ) as f32 *  CELL_SIZE * 0.5)   ;
-    let mut pos_x = start;
-    let mut pos_z = pos_x; 
-
-    for i in 0 .. TILES_COUNT   {
-        for j in 0 .. TILES_COUNT {
-            cmd.spawn((
-                Mesh3d(tile_mesh.clone()),
-                MeshMaterial3d(Handle::<StandardMaterial>::default()),
-                Transform::from_xyz(pos_x + center.x, 0. + center.y + CELL_HEIGHT / 2., pos_z + center.z),
-                Tile(i, j),
-                NotShadowCaster,
-                NotShadowReceiver,
-                Collider::cuboid(CELL_SIZE, CELL_HEIGHT, CELL_SIZE),
-                // Collider::cylinder(CELL_SIZE * 0.5, CELL_HEIGHT),
-                RigidBody::Static,
-                CollisionLayers::new(CoLayer::Tile, [LayerMask::ALL]),
-                Name::new("Tile")
-            ));
-            pos_z += CELL_SIZE;    
-        }
-        pos_x += CELL_SIZE;
-        pos_z = start;
-    }
+    // colours come from the chunk vertices, unlit stands in for the old per colour emissive materials
+    cmd.insert_resource(ValleyMaterial(materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        unlit: true,
+        ..default()
+    })));
     cmd.insert_resource(TilesCenter(PLAYER_START_CELL.0, PLAYER_START_CELL.1));
 
     cmd.spawn((
@@ -100,41 +78,156 @@ fn startup(
 
 // ---
 
+fn change_tiles_count(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut tiles: ResMut<TilesCount>
+) {
+    if keys.just_pressed(KeyCode::PageUp) {
+        tiles.0 = (tiles.0 + 10).min(MAX_TILES_COUNT);
+    }
+    if keys.just_pressed(KeyCode::PageDown) {
+        tiles.0 = (tiles.0 - 10).max(MIN_TILES_COUNT);
+    }
+}
+
+// ---
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn repaint (
     colors: Res<FractallCollors>,
-    mut tiles_q: Query<(&mut MeshMaterial3d<StandardMaterial>, &Tile, &mut Transform), Without<Player>>,
-    colorset: Res<MaterialSet>,
+    mut chunks: ResMut<ValleyChunks>,
+    mut chunk_q: Query<(Entity, &mut Chunk, &Mesh3d, &mut Collider, &mut TerraceBlocks, &mut Transform, Option<&TerraceMorph>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<ValleyMaterial>,
     tc: Res<TilesCenter>,
+    palette: Res<Palette>,
+    cycle: Res<PaletteCycle>,
+    heights: Res<HeightMap>,
+    shading: Res<DistanceShading>,
+    formula: Res<Formula>,
+    dive: Res<Dive>,
+    mut er: EventReader<WorldShift>,
     mut cmd: Commands
 ) {
+    let Some(window) = colors.range() else {
+        return;
+    };
+    let chunk_range = (
+        (window.0.0.div_euclid(CHUNK_CELLS), (window.0.1 - 1).div_euclid(CHUNK_CELLS)),
+        (window.1.0.div_euclid(CHUNK_CELLS), (window.1.1 - 1).div_euclid(CHUNK_CELLS))
+    );
+    // a world shift renames the cells, the chunks move with their cells instead of being rebuilt
+    let shift: IVec2 = er.read().map(|e| e.0).sum();
+    if shift != IVec2::ZERO {
+        // shifts are whole chunks, every chunk keeps its grid
+        let offset = Vec3::new(shift.x as f32 * CELL_SIZE, 0., shift.y as f32 * CELL_SIZE);
+        chunks.0 = chunks.0.drain().map(|(coord, e)| {
+            if let Ok((_, mut chunk, _, _, _, mut t, _)) = chunk_q.get_mut(e) {
+                t.translation -= offset;
+                chunk.0 = chunk.0.map(|c| ((c.0.0 - shift.x, c.0.1 - shift.x), (c.1.0 - shift.y, c.1.1 - shift.y)));
+            }
+            ((coord.0 - shift.x / CHUNK_CELLS, coord.1 - shift.y / CHUNK_CELLS), e)
+        }).collect();
+    }
+    chunks.0.retain(|coord, e| {
+        let keep = (chunk_range.0.0 ..= chunk_range.0.1).contains(&coord.0) && (chunk_range.1.0 ..= chunk_range.1.1).contains(&coord.1);
+        if !keep {
+            cmd.entity(*e).despawn_recursive();
+        }
+        keep
+    });
+
+    // the flag is stale when only the palette changed, new heights reshape every chunk
+    let rebuilt = (colors.is_changed() && colors.rebuilt) || heights.is_changed();
+    let recolor = palette.is_changed() || cycle.is_changed() || shading.is_changed();
+    let tile_colors = palette.tile_colors(&cycle);
+    let tint = |v: f32, d: f32| {
+        let color = tile_colors[palette.band(v)];
+        if shading.enabled {glow_tile(color, v, d)} else {color}
+    };
+    for cx in chunk_range.0.0 ..= chunk_range.0.1 {
+        for cz in chunk_range.1.0 ..= chunk_range.1.1 {
+            let clip = (
+                ((cx * CHUNK_CELLS).max(window.0.0), ((cx + 1) * CHUNK_CELLS).min(window.0.1)),
+                ((cz * CHUNK_CELLS).max(window.1.0), ((cz + 1) * CHUNK_CELLS).min(window.1.1))
+            );
+            let existing = chunks.0.get(&(cx, cz)).and_then(|e| chunk_q.get_mut(*e).ok());
+            let same_clip = existing.as_ref().is_some_and(|(_, chunk, ..)| chunk.0 == Some(clip));
+            if same_clip && !rebuilt {
+                // palette cycling only touches the vertex colours
+                if let Some((_, _, mesh3d, _, blocks, ..)) = existing.filter(|_| recolor) {
+                    if let Some(mesh) = meshes.get_mut(&mesh3d.0) {
+                        blocks.recolor(mesh, tint);
+                    }
+                }
+                continue;
+            }
 
-    let middle = TILES_COUNT / 2;
-
-    let (_, _, t,) = tiles_q.iter().find(|(_, tp, _)| {
-        tp.0 == middle && tp.1 == middle
-    }).unwrap();
-
-    let step = cell2xz((tc.0, tc.1)) - t.translation.with_y(0.);
-    // println!("step : {:?}", step);
-    for i in 0..TILES_COUNT{
-        for j in 0..TILES_COUNT {
-            if let Some((mut t_mat, _ , mut t_trans)) = tiles_q.iter_mut().find(|(_, tp, _)| {
-                tp.0 == i && tp.1 == j
-            }) {
-                let color_index = colors.0[i][j] as usize;
-                t_mat.0 =  colorset.0[color_index].clone();
-                t_trans.translation += step;
-                // t_trans.scale.y = 0.5 * (color_index + 1) as f32;
-                // t_trans.translation.y = t_trans.scale.y * CELL_HEIGHT / 2.;
-                t_trans.translation.y = color_index as f32 * 0.5;
-                
+            let mut mesh = TerraceMesh::default();
+            for x in clip.0.0 .. clip.0.1 {
+                for z in clip.1.0 .. clip.1.1 {
+                    let (value, distance) = (colors.get((x, z)), colors.distance((x, z)));
+                    let color = Color::from(LinearRgba::from_f32_array(tint(value, distance)));
+                    let top = heights.top(value, distance, formula.max_iter);
+                    mesh.add_block(&cell_block((x, z), 1, value, distance, top, color), false);
+                }
+            }
+            match existing {
+                Some((e, mut chunk, mesh3d, mut c, mut blocks, mut t, old_morph)) => {
+                    chunk.0 = Some(clip);
+                    // the new vertices are in place, a shifted chunk goes back to where it was built
+                    if *t != Transform::IDENTITY {
+                        *t = Transform::IDENTITY;
+                    }
+                    // the trimesh rebuilds its whole bvh, keep it when the terraces kept their shape
+                    let same_shape = meshes.get(&mesh3d.0)
+                        .and_then(|m| m.attribute(Mesh::ATTRIBUTE_POSITION))
+                        .is_some_and(|p| matches!(p, VertexAttributeValues::Float32x3(old) if *old == mesh.positions));
+                    if !same_shape {
+                        *c = mesh.collider();
+                    }
+                    // a dive starts the new terraces at the old heights
+                    let morph = dive.active().then(|| mesh.blocks.morph_from(&blocks, old_morph)).flatten();
+                    *blocks = std::mem::take(&mut mesh.blocks);
+                    let mut new_mesh = mesh.into_mesh();
+                    match morph {
+                        Some(mut morph) => {
+                            blocks.raise(&mut new_mesh, &mut morph, dive.remaining());
+                            cmd.entity(e).insert(morph);
+                        },
+                        None if old_morph.is_some() => {
+                            cmd.entity(e).remove::<TerraceMorph>();
+                        },
+                        None => ()
+                    }
+                    meshes.insert(&mesh3d.0, new_mesh);
+                },
+                None => {
+                    let collider = mesh.collider();
+                    let blocks = std::mem::take(&mut mesh.blocks);
+                    let e = cmd.spawn((
+                        Mesh3d(meshes.add(mesh.into_mesh())),
+                        MeshMaterial3d(material.0.clone()),
+                        Transform::IDENTITY,
+                        Chunk(Some(clip)),
+                        NotShadowCaster,
+                        NotShadowReceiver,
+                        collider,
+                        blocks,
+                        RigidBody::Static,
+                        CollisionLayers::new(CoLayer::Tile, [LayerMask::ALL]),
+                        Name::new("Chunk")
+                    )).id();
+                    chunks.0.insert((cx, cz), e);
+                }
             }
         }
     }
-    let m_y = colors.0[middle][middle];
-
-    cmd.trigger(AdjustY(m_y as f32 * 0.5 + CELL_HEIGHT / 2. + 2.));
 
+    // after a world shift the player already stands on the same tile, a dive only lands once it is done
+    if rebuilt && shift == IVec2::ZERO && !dive.active() {
+        cmd.trigger(AdjustY(heights.cell_top(&colors, (tc.0, tc.1), formula.max_iter) + 2.));
+    }
 }
 
 // ---